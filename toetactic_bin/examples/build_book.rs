@@ -0,0 +1,56 @@
+//! Builds an opening book for the game to use on a grid size.
+//!
+//! Usage: `cargo run --release --example build_book -- <rows> <cols> [games] [plies]`
+//!
+//! The book is saved in the data directory the game reads books from, so
+//! the computer plays from it at the highest difficulty. Books are built by
+//! having the engine play against itself, which takes a while: with the
+//! defaults of 100 games of 8 plies each, each move is searched for 200 ms.
+
+use std::{env, fs, process, time::Duration};
+use toetactic_lib::{book::OpeningBook, SearchLimits, SearchOptions};
+
+fn main() {
+    let args: Vec<usize> = env::args()
+        .skip(1)
+        .map(|arg| arg.parse().unwrap_or_else(|_| usage()))
+        .collect();
+    let (rows, cols, games, plies) = match args[..] {
+        [rows, cols] => (rows, cols, 100, 8),
+        [rows, cols, games] => (rows, cols, games, 8),
+        [rows, cols, games, plies] => (rows, cols, games, plies),
+        _ => usage(),
+    };
+    if !(3..=7).contains(&rows) || !(3..=7).contains(&cols) {
+        eprintln!("the grid must be between 3x3 and 7x7");
+        process::exit(1);
+    }
+    // the game plays with a win length of the shorter side of the grid
+    let k = rows.min(cols);
+    let limits = SearchLimits::time(Duration::from_millis(200));
+    let book = OpeningBook::build(
+        rows,
+        cols,
+        k,
+        games,
+        plies,
+        limits,
+        &SearchOptions::default(),
+    )
+    .expect("the engine only searches ongoing games");
+    let Some(dir) = toetactic::data_dir() else {
+        eprintln!("failed to find a cache directory to save the book in");
+        process::exit(1);
+    };
+    let path = dir.join(format!("{rows}x{cols}k{k}.ttob"));
+    if let Err(err) = fs::create_dir_all(&dir).and_then(|_| book.save(&path)) {
+        eprintln!("failed to save the book: {err}");
+        process::exit(1);
+    }
+    println!("saved {} positions to {}", book.len(), path.display());
+}
+
+fn usage() -> ! {
+    eprintln!("usage: build_book <rows> <cols> [games] [plies]");
+    process::exit(2);
+}
@@ -15,7 +15,10 @@ use ratatui::{
     DefaultTerminal, Frame,
 };
 use std::{thread, time::Duration};
-use toetactic_lib::mech::{Game, GameState, Player};
+use toetactic_lib::{
+    engine::Skilled,
+    mech::{Game, GameState, Player},
+};
 
 #[derive(Debug, Default)]
 pub struct App {
@@ -117,16 +120,20 @@ impl App {
         if let Some(ref st) = self.state.pregame {
             let mut s = (*st).borrow_mut();
             match key {
-                KeyCode::Up | KeyCode::Right | KeyCode::Char('k') => {
-                    if s.grid_size < consts::MAX_GRID_SIZE {
-                        s.grid_size += 1
-                    }
+                KeyCode::Up | KeyCode::Char('k') if s.rows < consts::MAX_GRID_SIZE => {
+                    s.rows += 1
                 }
-                KeyCode::Down | KeyCode::Left | KeyCode::Char('j') => {
-                    if s.grid_size > consts::MIN_GRID_SIZE {
-                        s.grid_size -= 1
-                    }
+                KeyCode::Down | KeyCode::Char('j') if s.rows > consts::MIN_GRID_SIZE => {
+                    s.rows -= 1
+                }
+                KeyCode::Right | KeyCode::Char('l') if s.cols < consts::MAX_GRID_SIZE => {
+                    s.cols += 1
                 }
+                KeyCode::Left | KeyCode::Char('h') if s.cols > consts::MIN_GRID_SIZE => {
+                    s.cols -= 1
+                }
+                KeyCode::Char('+' | '=') if s.level < Skilled::MAX_LEVEL => s.level += 1,
+                KeyCode::Char('-') if s.level > 1 => s.level -= 1,
                 KeyCode::Enter => {
                     self.state.pregame_confirm =
                         Some(helpers::rfc(pregame_confirm::PregameConfirmState {
@@ -187,14 +194,18 @@ impl App {
             if let (Some(turn), Some(pregame_st)) =
                 (&self.state.pregame_confirm, &self.state.pregame)
             {
+                let pregame::PregameState { rows, cols, level } = *pregame_st.borrow();
                 self.state.ingame = Some(helpers::amtx(ingame::IngameState {
-                    game: Game::new(pregame_st.borrow().grid_size),
+                    game: Game::new(rows, cols),
                     user: match turn.borrow().option_state {
                         X => Player::X,
                         O => Player::O,
                     },
+                    level,
                     selected: (0, 0),
                     inthread: false,
+                    error: None,
+                    stop: Default::default(),
                 }));
                 self.current_screen = CurrentScreen::Ingame;
             }
@@ -210,12 +221,21 @@ impl App {
             if s.game.state() == GameState::Ongoing && s.user != s.game.turn() && !s.inthread {
                 s.inthread = true;
                 let game = s.game.clone();
+                let level = s.level;
+                let stop = s.stop.clone();
                 let state = helpers::pass_atomic(st);
                 thread::spawn(move || {
-                    let best =
-                        toetactic_lib::get_best_move(&game, consts::SIZE_DEPTHS[game.grid().n()]);
+                    let (rows, cols) = (game.grid().rows(), game.grid().cols());
+                    let best_move = helpers::opponent(rows, cols, level, &stop).choose_move(&game);
                     let mut st = state.lock().unwrap();
-                    st.game.play(best).unwrap();
+                    if stop.is_stopped() {
+                        // the game has been abandoned
+                        return;
+                    }
+                    if let Ok(mv) = best_move {
+                        // book moves and the engine's moves are always empty cells
+                        let _ = st.game.play(mv);
+                    }
                     st.inthread = false;
                 });
             }
@@ -225,21 +245,20 @@ impl App {
     fn scr_ingame_handle_key(&mut self, key: KeyCode) {
         if let Some(ref st) = self.state.ingame {
             let mut s = st.lock().unwrap();
+            s.error = None;
             let (r, c) = s.selected;
-            let maxrc = s.game.grid().n() - 1;
+            let (maxr, maxc) = (s.game.grid().rows() - 1, s.game.grid().cols() - 1);
             let left = (r, c.saturating_sub(1));
-            let down = (if r < maxrc { r + 1 } else { r }, c);
+            let down = (if r < maxr { r + 1 } else { r }, c);
             let up = (r.saturating_sub(1), c);
-            let right = (r, if c < maxrc { c + 1 } else { c });
+            let right = (r, if c < maxc { c + 1 } else { c });
             match key {
                 KeyCode::Left | KeyCode::Char('h') => s.selected = left,
                 KeyCode::Down | KeyCode::Char('j') => s.selected = down,
                 KeyCode::Up | KeyCode::Char('k') => s.selected = up,
                 KeyCode::Right | KeyCode::Char('l') => s.selected = right,
-                KeyCode::Char(' ') => {
-                    if s.user == s.game.turn() {
-                        s.game.play((r, c));
-                    }
+                KeyCode::Char(' ') if s.user == s.game.turn() => {
+                    s.error = s.game.play((r, c)).err();
                 }
                 KeyCode::Char('r') => {
                     self.state.startover = Some(helpers::rfc(startover::StartoverState::default()));
@@ -293,7 +312,10 @@ impl App {
             if let Some(st) = &self.state.startover {
                 match *st.borrow() {
                     Stay => self.current_screen = CurrentScreen::Ingame,
-                    StartOver => self.current_screen = CurrentScreen::Pregame,
+                    StartOver => {
+                        self.stop_engine();
+                        self.current_screen = CurrentScreen::Pregame;
+                    }
                 }
             }
         }
@@ -355,12 +377,19 @@ impl App {
                 self.current_screen = *prev.clone();
                 let mut st = s.borrow_mut();
                 if *st == Leave {
+                    self.stop_engine();
                     *st = Left;
                 }
             }
         }
     }
 
+    fn stop_engine(&self) {
+        if let Some(ref st) = self.state.ingame {
+            st.lock().unwrap().stop.stop();
+        }
+    }
+
     fn exit(&mut self) {
         if !matches!(self.current_screen, CurrentScreen::Exiting(_)) {
             self.current_screen = CurrentScreen::Exiting(Box::new(self.current_screen.clone()));
@@ -1,8 +1,11 @@
 use ratatui::style::Color;
+use std::time::Duration;
 
 pub const MIN_GRID_SIZE: usize = 3;
 pub const MAX_GRID_SIZE: usize = 7;
-pub const SIZE_DEPTHS: [usize; MAX_GRID_SIZE + 1] = [0, 0, 0, 6, 9, 6, 5, 5];
+pub const DEFAULT_LEVEL: u8 = toetactic_lib::engine::Skilled::MAX_LEVEL;
+pub const THINKING_TIME: Duration = Duration::from_millis(1500);
+pub const DATA_DIR: &str = "toetactic";
 
 pub const BGCOLOR: Color = Color::Rgb(26, 26, 26);
 pub const INSTRUCTIONS_COLOR: Color = Color::LightBlue;
@@ -11,6 +14,8 @@ pub const PREGAME_GRID_SIZE: f64 = 0.8;
 pub const PREGAME_CONFIRM_TEXT: &str = "Would you like to play X or O? (X plays first)";
 
 pub const INGAME_GRID_SIZE: f64 = 0.8;
+pub const INGAME_WIN_COLOR: Color = Color::Rgb(46, 86, 46);
+pub const INGAME_BLOCK_COLOR: Color = Color::Rgb(96, 46, 46);
 pub const STARTOVER_TEXT: &str = "Are you sure you want to start over?";
 
 pub const EXIT_CONFIRM_TEXT: &str = "Are you sure you want to exit?";
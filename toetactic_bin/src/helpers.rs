@@ -1,7 +1,13 @@
+use crate::consts;
 use ratatui::{layout::{Constraint, Flex, Layout, Rect}, symbols::{border, line}, widgets::Borders};
-use std::{cell::RefCell, rc::Rc, sync::{Arc, Mutex}};
+use std::{cell::RefCell, cmp, env, fs, path::PathBuf, rc::Rc, sync::{Arc, Mutex}, thread};
+use toetactic_lib::{
+    book::OpeningBook,
+    engine::{Engine, Minimax, Skilled},
+    tablebase::Tablebase,
+    SearchLimits, SearchOptions, StopToken,
+};
 
-pub type AppResult = std::io::Result<()>;
 pub type Rfc<T> = Rc<RefCell<T>>;
 pub type Amtx<T> = Arc<Mutex<T>>;
 
@@ -28,12 +34,122 @@ pub fn centered_scale(area: Rect, horiz_scale: f64, vert_scale: f64) -> Rect {
     }
 }
 
+// cells are twice as wide as they are tall so that they look square in a terminal
+pub fn grid_dimensions(area: Rect, rows: usize, cols: usize, scale: f64) -> (u16, u16) {
+    let cell_height = f64::min(
+        area.width as f64 / (2 * cols) as f64,
+        area.height as f64 / rows as f64,
+    ) * scale;
+    (
+        (cell_height * (2 * cols) as f64).round() as u16,
+        (cell_height * rows as f64).round() as u16,
+    )
+}
+
 pub fn center(area: Rect, horiz: Constraint, vert: Constraint) -> Rect {
     let [area] = Layout::horizontal([horiz]).flex(Flex::Center).areas(area);
     let [area] = Layout::vertical([vert]).flex(Flex::Center).areas(area);
     area
 }
 
+// the computer's player, which stops thinking once `stop` is stopped
+pub fn opponent(rows: usize, cols: usize, level: u8, stop: &StopToken) -> Box<dyn Engine + Send> {
+    let limits = SearchLimits::time(consts::THINKING_TIME);
+    let options = SearchOptions {
+        stop: Some(stop.clone()),
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        ..Default::default()
+    };
+    if level < Skilled::MAX_LEVEL {
+        // without the tablebase, since it would let every level see to the end of the game
+        return Box::new(Skilled::with_options(level, limits, options));
+    }
+    let options = SearchOptions {
+        tablebase: tablebase(rows, cols),
+        ..options
+    };
+    let mut minimax = Minimax::with_options(limits, options);
+    minimax.book = book(rows, cols);
+    Box::new(minimax)
+}
+
+// tablebases are built in the background the first time a grid size is
+// played, and cached on disk. until one is ready, the computer plays without it
+pub fn tablebase(rows: usize, cols: usize) -> Option<Arc<Tablebase>> {
+    type Entry = ((usize, usize, usize), Option<Arc<Tablebase>>);
+    static TABLEBASES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+    if rows * cols > Tablebase::MAX_CELLS {
+        return None;
+    }
+    let k = cmp::min(rows, cols);
+    {
+        let mut tablebases = TABLEBASES.lock().unwrap();
+        if let Some((_, tb)) = tablebases.iter().find(|(size, _)| *size == (rows, cols, k)) {
+            return tb.clone();
+        }
+        // so that it's only built once
+        tablebases.push(((rows, cols, k), None));
+    }
+    thread::spawn(move || {
+        let path = data_dir().map(|dir| dir.join(format!("{rows}x{cols}k{k}.ttb")));
+        let tb = match path.as_ref().map(Tablebase::load) {
+            Some(Ok(tb)) if (tb.rows(), tb.cols(), tb.win_length()) == (rows, cols, k) => tb,
+            _ => {
+                let tb = Tablebase::generate(rows, cols, k);
+                // the tablebase is only rebuilt next time if saving fails
+                if let Some(path) = path {
+                    let _ = fs::create_dir_all(path.parent().unwrap()).and_then(|_| tb.save(&path));
+                }
+                tb
+            }
+        };
+        let mut tablebases = TABLEBASES.lock().unwrap();
+        if let Some((_, entry)) = tablebases
+            .iter_mut()
+            .find(|(size, _)| *size == (rows, cols, k))
+        {
+            *entry = Some(Arc::new(tb));
+        }
+    });
+    None
+}
+
+// the directory tablebases and opening books are cached in. it's per user,
+// since anyone can replace files in the system's temporary directory
+pub fn data_dir() -> Option<PathBuf> {
+    let cache = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    cache.map(|dir| dir.join(consts::DATA_DIR))
+}
+
+// opening books aren't built by the game, but are used if one has been saved
+// in the data directory, which `cargo run --release --example build_book` does.
+// a book that's missing is only looked for once
+pub fn book(rows: usize, cols: usize) -> Option<Arc<OpeningBook>> {
+    type Entry = ((usize, usize, usize), Option<Arc<OpeningBook>>);
+    static BOOKS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+    let k = cmp::min(rows, cols);
+    let mut books = BOOKS.lock().unwrap();
+    if let Some((_, book)) = books.iter().find(|(size, _)| *size == (rows, cols, k)) {
+        return book.clone();
+    }
+    let path = data_dir().map(|dir| dir.join(format!("{rows}x{cols}k{k}.ttob")));
+    let book = match path.map(OpeningBook::load) {
+        Some(Ok(book)) if (book.rows(), book.cols(), book.win_length()) == (rows, cols, k) => {
+            Some(Arc::new(book))
+        }
+        _ => None,
+    };
+    books.push(((rows, cols, k), book.clone()));
+    book
+}
+
 pub fn rfc<T>(v: T) -> Rfc<T> {
     Rc::new(RefCell::new(v))
 }
@@ -53,7 +169,8 @@ pub fn pass_atomic<T>(state: &Amtx<T>) -> Amtx<T> {
 pub fn get_collapsed_borders(
     r: usize,
     c: usize,
-    gsize: usize,
+    rows: usize,
+    cols: usize,
     bset: border::Set,
     lset: line::Set,
     ttt: bool,
@@ -70,7 +187,7 @@ pub fn get_collapsed_borders(
                 },
                 bset,
             )
-        } else if c + 1 == gsize {
+        } else if c + 1 == cols {
             // last column
             (
                 Borders::LEFT
@@ -94,7 +211,7 @@ pub fn get_collapsed_borders(
                 },
             )
         }
-    } else if r + 1 == gsize {
+    } else if r + 1 == rows {
         // last row
         if c == 0 {
             // first column
@@ -110,7 +227,7 @@ pub fn get_collapsed_borders(
                     ..bset
                 },
             )
-        } else if c + 1 == gsize {
+        } else if c + 1 == cols {
             // last column
             (
                 Borders::TOP
@@ -149,7 +266,7 @@ pub fn get_collapsed_borders(
                     ..bset
                 },
             )
-        } else if c + 1 == gsize {
+        } else if c + 1 == cols {
             // last column
             (
                 Borders::LEFT | Borders::TOP | if ttt { Borders::NONE } else { Borders::RIGHT },
@@ -5,5 +5,6 @@ mod helpers;
 mod screens;
 
 pub use app::App;
+pub use helpers::data_dir;
 
 pub type AppResult = std::io::Result<()>;
@@ -7,7 +7,10 @@ use ratatui::{
     text::{Line, Span},
     widgets::{Block, Paragraph, Widget},
 };
-use toetactic_lib::mech::{self, Game, GameState, Move, Player};
+use toetactic_lib::{
+    mech::{self, Game, GameState, Move, PlayError, Player},
+    StopToken,
+};
 
 pub fn instructions() -> Vec<Span<'static>> {
     vec![
@@ -24,28 +27,26 @@ pub fn instructions() -> Vec<Span<'static>> {
 pub struct IngameState {
     pub game: Game,
     pub user: Player,
+    pub level: u8,
     pub selected: Move,
     pub inthread: bool,
+    pub error: Option<PlayError>,
+    pub stop: StopToken,
 }
 
 pub struct IngameWidget(pub helpers::Amtx<IngameState>);
 
 impl Widget for &IngameWidget {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let (gwidth, gheight) = if area.width * 2 < area.height {
-            let w = area.width as f64 * consts::INGAME_GRID_SIZE;
-            (w.round() as u16, (w / 2.).round() as u16)
-        } else {
-            let h = area.height as f64 * consts::INGAME_GRID_SIZE;
-            ((h * 2.).round() as u16, h.round() as u16)
-        };
+        let st = self.0.lock().unwrap();
+        let (nrows, ncols) = (st.game.grid().rows(), st.game.grid().cols());
+        let (gwidth, gheight) =
+            helpers::grid_dimensions(area, nrows, ncols, consts::INGAME_GRID_SIZE);
         let garea = helpers::center(
             area,
             Constraint::Length(gwidth),
             Constraint::Length(gheight),
         );
-        let st = self.0.lock().unwrap();
-        let grid_size = st.game.grid().n();
         Block::default()
             .title(
                 Line::from(if st.game.state() != GameState::Ongoing {
@@ -62,6 +63,8 @@ impl Widget for &IngameWidget {
                         }
                         .into(),
                     ]
+                } else if let Some(err) = st.error {
+                    vec!["Your turn".into(), ": ".into(), format!("{err}").light_red()]
                 } else if st.game.turn() == st.user {
                     vec!["Your turn".into()]
                 } else {
@@ -70,14 +73,21 @@ impl Widget for &IngameWidget {
                 .centered(),
             )
             .render(helpers::centered_scale(garea, 1.15, 1.15), buf);
-        let rows = Layout::vertical((0..grid_size).map(|_| Constraint::Fill(1))).split(garea);
+        // the cells the user can win in or has to block are highlighted on their turn
+        let threats = if st.game.turn() == st.user {
+            st.game.threats()
+        } else {
+            Default::default()
+        };
+        let rows = Layout::vertical((0..nrows).map(|_| Constraint::Fill(1))).split(garea);
         for (r, &row) in rows.iter().enumerate() {
-            let cols = Layout::horizontal((0..grid_size).map(|_| Constraint::Fill(1))).split(row);
+            let cols = Layout::horizontal((0..ncols).map(|_| Constraint::Fill(1))).split(row);
             for (c, &cell) in cols.iter().enumerate() {
                 let (borders, border_set) = helpers::get_collapsed_borders(
                     r,
                     c,
-                    grid_size,
+                    nrows,
+                    ncols,
                     border::PLAIN,
                     line::NORMAL,
                     true,
@@ -111,6 +121,18 @@ impl Widget for &IngameWidget {
                         buf,
                     );
                 }
+                let highlight = if threats.player(st.user).wins.contains(&(r, c)) {
+                    Some(consts::INGAME_WIN_COLOR)
+                } else if threats.blocks.contains(&(r, c)) {
+                    Some(consts::INGAME_BLOCK_COLOR)
+                } else {
+                    None
+                };
+                if let Some(color) = highlight {
+                    Block::new()
+                        .bg(color)
+                        .render(helpers::centered_scale(cell, 0.75, 0.75), buf);
+                }
                 if st.selected == (r, c)
                     && st.game.turn() == st.user
                     && st.game.state() == GameState::Ongoing
@@ -7,11 +7,16 @@ use ratatui::{
     text::{Line, Span},
     widgets::{Block, Widget},
 };
+use toetactic_lib::engine::Skilled;
 
 pub fn instructions() -> Vec<Span<'static>> {
     vec![
         " ↑↓".bold().fg(consts::INSTRUCTIONS_COLOR),
-        " Change grid".into(),
+        " Change rows".into(),
+        "  ←→".bold().fg(consts::INSTRUCTIONS_COLOR),
+        " Change columns".into(),
+        "  +-".bold().fg(consts::INSTRUCTIONS_COLOR),
+        " Change difficulty".into(),
         "  ⏎".bold().fg(consts::INSTRUCTIONS_COLOR),
         " Select grid ".into(),
     ]
@@ -19,13 +24,17 @@ pub fn instructions() -> Vec<Span<'static>> {
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub struct PregameState {
-    pub grid_size: usize,
+    pub rows: usize,
+    pub cols: usize,
+    pub level: u8,
 }
 
 impl Default for PregameState {
     fn default() -> Self {
         Self {
-            grid_size: consts::MIN_GRID_SIZE,
+            rows: consts::MIN_GRID_SIZE,
+            cols: consts::MIN_GRID_SIZE,
+            level: consts::DEFAULT_LEVEL,
         }
     }
 }
@@ -34,28 +43,40 @@ pub struct PregameWidget(pub helpers::Rfc<PregameState>);
 
 impl Widget for &PregameWidget {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let (gwidth, gheight) = if area.width * 2 < area.height {
-            let w = area.width as f64 * consts::PREGAME_GRID_SIZE;
-            (w.round() as u16, (w / 2.).round() as u16)
-        } else {
-            let h = area.height as f64 * consts::PREGAME_GRID_SIZE;
-            ((h * 2.).round() as u16, h.round() as u16)
-        };
+        let PregameState {
+            rows: nrows,
+            cols: ncols,
+            level,
+        } = *(*self.0).borrow();
+        let (gwidth, gheight) =
+            helpers::grid_dimensions(area, nrows, ncols, consts::PREGAME_GRID_SIZE);
         let garea = helpers::center(
             area,
             Constraint::Length(gwidth),
             Constraint::Length(gheight),
         );
-        let grid_size = (*self.0).borrow().grid_size;
         Block::default()
-            .title(Line::from(format!("Choose your grid: {grid_size}x{grid_size}")).centered())
+            .title(
+                Line::from(format!(
+                    "Choose your grid: {nrows}x{ncols}, difficulty {level}/{}",
+                    Skilled::MAX_LEVEL
+                ))
+                .centered(),
+            )
             .render(helpers::centered_scale(garea, 1.15, 1.15), buf);
-        let rows = Layout::vertical((0..grid_size).map(|_| Constraint::Fill(1))).split(garea);
+        let rows = Layout::vertical((0..nrows).map(|_| Constraint::Fill(1))).split(garea);
         for (r, &row) in rows.iter().enumerate() {
-            let cols = Layout::horizontal((0..grid_size).map(|_| Constraint::Fill(1))).split(row);
+            let cols = Layout::horizontal((0..ncols).map(|_| Constraint::Fill(1))).split(row);
             for (c, &cell) in cols.iter().enumerate() {
-                let (borders, border_set) =
-                    helpers::get_collapsed_borders(r, c, grid_size, border::PLAIN, line::NORMAL, true);
+                let (borders, border_set) = helpers::get_collapsed_borders(
+                    r,
+                    c,
+                    nrows,
+                    ncols,
+                    border::PLAIN,
+                    line::NORMAL,
+                    true,
+                );
                 Block::new()
                     .borders(borders)
                     .border_set(border_set)
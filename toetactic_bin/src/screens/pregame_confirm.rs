@@ -43,9 +43,9 @@ impl Widget for &PregameConfirmWidget {
         };
         let options = Line::from(opts);
         Clear.render(area, buf);
-        let gsize = state.pregame_state.grid_size;
+        let pregame::PregameState { rows, cols, .. } = state.pregame_state;
         Block::bordered()
-            .title(Line::from(format!(" {gsize}x{gsize} Tic Tac Toe ").bold()).centered())
+            .title(Line::from(format!(" {rows}x{cols} Tic Tac Toe ").bold()).centered())
             .title_bottom(options.centered())
             .bg(consts::BGCOLOR)
             .border_set(border::ROUNDED)
@@ -0,0 +1,726 @@
+//! Bitboard representation of positions, used for fast searching
+
+use crate::{
+    mech::{Cell, GameState, Move, Player},
+    symmetry::{self, Transform},
+};
+use std::{
+    fmt::{self, Formatter},
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, Not},
+    sync::Arc,
+};
+
+/// The largest number of cells a grid may have.
+pub const MAX_CELLS: usize = 256;
+
+const WORDS: usize = MAX_CELLS / 64;
+
+/// Represents a set of cells, one bit per cell, where cell
+/// (`row`, `col`) is bit `row * cols + col`.
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub struct Bits([u64; WORDS]);
+
+impl Bits {
+    /// The empty set.
+    pub const EMPTY: Self = Self([0; WORDS]);
+
+    /// Returns the set containing only `cell`.
+    pub fn single(cell: usize) -> Self {
+        let mut bits = Self::EMPTY;
+        bits.set(cell);
+        bits
+    }
+
+    /// Adds `cell` to the set.
+    pub fn set(&mut self, cell: usize) {
+        self.0[cell / 64] |= 1 << (cell % 64);
+    }
+
+    /// Removes `cell` from the set.
+    pub fn clear(&mut self, cell: usize) {
+        self.0[cell / 64] &= !(1 << (cell % 64));
+    }
+
+    /// Returns `true` if `cell` is in the set.
+    pub fn contains(&self, cell: usize) -> bool {
+        self.0[cell / 64] & (1 << (cell % 64)) != 0
+    }
+
+    /// Returns `true` if the set has no cells.
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+
+    /// Returns `true` if every cell in the set is also in `other`.
+    pub fn is_subset(&self, other: Self) -> bool {
+        self.0.iter().zip(other.0).all(|(&a, b)| a & !b == 0)
+    }
+
+    /// Returns the number of cells in the set.
+    pub fn count(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Returns the lowest cell in the set, if there is one.
+    pub fn first(&self) -> Option<usize> {
+        self.0
+            .iter()
+            .enumerate()
+            .find(|(_, &w)| w != 0)
+            .map(|(i, w)| i * 64 + w.trailing_zeros() as usize)
+    }
+
+    /// Returns an iterator over the cells in the set, lowest first.
+    pub fn iter(&self) -> BitsIter {
+        BitsIter(*self)
+    }
+}
+
+impl BitAnd for Bits {
+    type Output = Self;
+
+    fn bitand(mut self, rhs: Self) -> Self::Output {
+        self &= rhs;
+        self
+    }
+}
+
+impl BitAndAssign for Bits {
+    fn bitand_assign(&mut self, rhs: Self) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a &= b;
+        }
+    }
+}
+
+impl BitOr for Bits {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: Self) -> Self::Output {
+        self |= rhs;
+        self
+    }
+}
+
+impl BitOrAssign for Bits {
+    fn bitor_assign(&mut self, rhs: Self) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a |= b;
+        }
+    }
+}
+
+impl BitXor for Bits {
+    type Output = Self;
+
+    fn bitxor(mut self, rhs: Self) -> Self::Output {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a ^= b;
+        }
+        self
+    }
+}
+
+impl Not for Bits {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        Self(self.0.map(|w| !w))
+    }
+}
+
+impl IntoIterator for Bits {
+    type Item = usize;
+    type IntoIter = BitsIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        BitsIter(self)
+    }
+}
+
+/// An iterator over the cells in a [`Bits`], lowest first.
+#[derive(Debug, Clone)]
+pub struct BitsIter(Bits);
+
+impl Iterator for BitsIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cell = self.0.first()?;
+        self.0.clear(cell);
+        Some(cell)
+    }
+}
+
+/// The seed from which the Zobrist keys are generated. The keys only
+/// depend on this seed, so hashes are the same across runs.
+const ZOBRIST_SEED: u64 = 0x746f_6574_6163_7469;
+
+/// Returns the next number from a SplitMix64 generator with the given state.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Precomputed data about a grid size and win length: the mask of
+/// every line a player could fill to win, which lines pass through
+/// each cell, and the Zobrist keys used to hash positions.
+pub struct Geometry {
+    rows: usize,
+    cols: usize,
+    k: usize,
+    board: Bits,
+    lines: Vec<Bits>,
+    cell_lines: Vec<Vec<usize>>,
+    keys: Vec<[u64; 2]>,
+    turn_key: u64,
+}
+
+impl Geometry {
+    /// Computes the geometry of a `rows` by `cols` grid where a player
+    /// wins with `k` marks in a row.
+    /// **Panics** if the grid has more than [`MAX_CELLS`] cells.
+    pub fn new(rows: usize, cols: usize, k: usize) -> Self {
+        assert!(rows * cols <= MAX_CELLS);
+        let mut board = Bits::EMPTY;
+        for cell in 0..rows * cols {
+            board.set(cell);
+        }
+        let (irows, icols, ik) = (rows as isize, cols as isize, k as isize);
+        let mut lines = Vec::new();
+        let mut cell_lines = vec![Vec::new(); rows * cols];
+        // right, down, down-right (\) and down-left (/)
+        for (dr, dc) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for i in 0..irows {
+                for j in 0..icols {
+                    let (endr, endc) = (i + dr * (ik - 1), j + dc * (ik - 1));
+                    if endr < 0 || endr >= irows || endc < 0 || endc >= icols {
+                        continue;
+                    }
+                    let mut line = Bits::EMPTY;
+                    for step in 0..ik {
+                        let cell = ((i + dr * step) * icols + j + dc * step) as usize;
+                        line.set(cell);
+                        cell_lines[cell].push(lines.len());
+                    }
+                    lines.push(line);
+                }
+            }
+        }
+        let mut state = ZOBRIST_SEED;
+        let keys = (0..rows * cols)
+            .map(|_| [splitmix64(&mut state), splitmix64(&mut state)])
+            .collect();
+        let turn_key = splitmix64(&mut state);
+        Self {
+            rows,
+            cols,
+            k,
+            board,
+            lines,
+            cell_lines,
+            keys,
+            turn_key,
+        }
+    }
+
+    /// Returns the number of rows in the grid.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the number of columns in the grid.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Returns the number of marks a player needs in a row to win.
+    pub fn win_length(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the number of cells in the grid.
+    pub fn cells(&self) -> usize {
+        self.rows * self.cols
+    }
+
+    /// Returns the set of all cells in the grid.
+    pub fn board(&self) -> Bits {
+        self.board
+    }
+
+    /// Returns the masks of every line a player could fill to win.
+    pub fn lines(&self) -> &[Bits] {
+        &self.lines
+    }
+
+    /// Returns the indices (into [`Geometry::lines`]) of the lines
+    /// passing through `cell`.
+    pub fn lines_through(&self, cell: usize) -> &[usize] {
+        &self.cell_lines[cell]
+    }
+
+    /// Converts a move into a cell index.
+    pub fn index(&self, mv: Move) -> usize {
+        mv.0 * self.cols + mv.1
+    }
+
+    /// Converts a cell index into a move.
+    pub fn to_move(&self, cell: usize) -> Move {
+        (cell / self.cols, cell % self.cols)
+    }
+
+    /// Returns where `cell` ends up when the grid is transformed.
+    /// **Panics** if the transform doesn't apply to the grid.
+    pub fn transform(&self, t: Transform, cell: usize) -> usize {
+        self.index(t.apply(self.to_move(cell), self.rows, self.cols))
+    }
+}
+
+impl fmt::Debug for Geometry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Geometry")
+            .field("rows", &self.rows)
+            .field("cols", &self.cols)
+            .field("k", &self.k)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Geometry {
+    fn eq(&self, other: &Self) -> bool {
+        (self.rows, self.cols, self.k) == (other.rows, other.cols, other.k)
+    }
+}
+
+impl Eq for Geometry {}
+
+/// Returns the index into per-player arrays for `player`.
+fn side(player: Player) -> usize {
+    match player {
+        Player::X => 0,
+        Player::O => 1,
+    }
+}
+
+/// Represents a position as one bitboard per player. Moves are
+/// played with [`Position::make`] and taken back with
+/// [`Position::unmake`], so a search never has to copy the board.
+///
+/// Alongside the bitboards, the position keeps a count of each
+/// player's marks on every line, and for each player, how many lines
+/// each empty cell would complete. These are updated only for the
+/// lines through the cell being played, so detecting wins and
+/// obvious moves doesn't require scanning the whole grid.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Position {
+    geometry: Arc<Geometry>,
+    marks: [Bits; 2],
+    turn: Player,
+    winner: Option<Player>,
+    hash: u64,
+    /// The number of marks each player has on each line.
+    counts: Vec<[u8; 2]>,
+    threats: [Threats; 2],
+}
+
+/// Keeps track of the cells that would complete a line for a player.
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct Threats {
+    /// The number of lines each cell would complete.
+    counts: Vec<u8>,
+    /// The cells that would complete at least one line.
+    cells: Bits,
+}
+
+impl Threats {
+    fn new(cells: usize) -> Self {
+        Self {
+            counts: vec![0; cells],
+            cells: Bits::EMPTY,
+        }
+    }
+
+    fn add(&mut self, cell: usize) {
+        self.counts[cell] += 1;
+        self.cells.set(cell);
+    }
+
+    fn remove(&mut self, cell: usize) {
+        self.counts[cell] -= 1;
+        if self.counts[cell] == 0 {
+            self.cells.clear(cell);
+        }
+    }
+}
+
+impl Position {
+    /// Creates an empty position on a `rows` by `cols` grid where a
+    /// player wins with `k` marks in a row.
+    /// **Panics** if the grid has more than [`MAX_CELLS`] cells.
+    pub fn new(rows: usize, cols: usize, k: usize) -> Self {
+        let geometry = Geometry::new(rows, cols, k);
+        Self {
+            marks: [Bits::EMPTY; 2],
+            turn: Player::X,
+            winner: None,
+            hash: 0,
+            counts: vec![[0; 2]; geometry.lines.len()],
+            threats: [
+                Threats::new(geometry.cells()),
+                Threats::new(geometry.cells()),
+            ],
+            geometry: Arc::new(geometry),
+        }
+    }
+
+    /// Returns the geometry of the grid.
+    pub fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    /// Returns the player whose turn it is to move.
+    pub fn turn(&self) -> Player {
+        self.turn
+    }
+
+    /// Returns the Zobrist hash of the position, which identifies the
+    /// marks on the grid and the side to move.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Returns the cells marked by `player`.
+    pub fn marks(&self, player: Player) -> Bits {
+        self.marks[side(player)]
+    }
+
+    /// Returns the cells marked by either player.
+    pub fn occupied(&self) -> Bits {
+        self.marks[0] | self.marks[1]
+    }
+
+    /// Returns the cells that are still empty.
+    pub fn empty(&self) -> Bits {
+        self.geometry.board & !self.occupied()
+    }
+
+    /// Returns the contents of `cell`.
+    pub fn cell(&self, cell: usize) -> Cell {
+        if self.marks[0].contains(cell) {
+            Cell::X
+        } else if self.marks[1].contains(cell) {
+            Cell::O
+        } else {
+            Cell::Empty
+        }
+    }
+
+    /// Returns the game state.
+    pub fn state(&self) -> GameState {
+        if let Some(winner) = self.winner {
+            GameState::Decisive(winner)
+        } else if self.occupied() == self.geometry.board {
+            GameState::Tied
+        } else {
+            GameState::Ongoing
+        }
+    }
+
+    /// Plays the side to move's mark in `cell`, **assuming** the game
+    /// is ongoing and `cell` is empty.
+    pub fn make(&mut self, cell: usize) {
+        debug_assert!(self.winner.is_none() && !self.occupied().contains(cell));
+        let (p, q) = (side(self.turn), side(!self.turn));
+        let k = self.geometry.k as u8;
+        self.marks[p].set(cell);
+        self.hash ^= self.geometry.keys[cell][p] ^ self.geometry.turn_key;
+        let occupied = self.occupied();
+        for &line in self.geometry.lines_through(cell) {
+            let [cp, cq] = [self.counts[line][p], self.counts[line][q]];
+            if cq == 0 && cp == k - 1 {
+                // the line is completed
+                self.threats[p].remove(cell);
+                self.winner = Some(self.turn);
+            } else if cp == 0 && cq == k - 1 {
+                // the opponent's line is blocked
+                self.threats[q].remove(cell);
+            } else if cq == 0 && cp == k - 2 {
+                // the line is one mark away from being completed
+                let missing = self.geometry.lines[line] & !occupied;
+                self.threats[p].add(missing.first().unwrap());
+            }
+            self.counts[line][p] += 1;
+        }
+        self.turn = !self.turn;
+    }
+
+    /// Takes back the mark in `cell`, **assuming** it was the last
+    /// move made.
+    pub fn unmake(&mut self, cell: usize) {
+        self.turn = !self.turn;
+        let (p, q) = (side(self.turn), side(!self.turn));
+        let k = self.geometry.k as u8;
+        let occupied = self.occupied();
+        for &line in self.geometry.lines_through(cell) {
+            self.counts[line][p] -= 1;
+            let [cp, cq] = [self.counts[line][p], self.counts[line][q]];
+            if cq == 0 && cp == k - 1 {
+                self.threats[p].add(cell);
+            } else if cp == 0 && cq == k - 1 {
+                self.threats[q].add(cell);
+            } else if cq == 0 && cp == k - 2 {
+                // `cell` is still marked, so the only empty cell is the missing one
+                let missing = self.geometry.lines[line] & !occupied;
+                self.threats[p].remove(missing.first().unwrap());
+            }
+        }
+        self.marks[p].clear(cell);
+        self.hash ^= self.geometry.keys[cell][p] ^ self.geometry.turn_key;
+        self.winner = None;
+    }
+
+    /// Returns the number of marks X and O have on each line, in the
+    /// order of [`Geometry::lines`].
+    pub fn line_counts(&self) -> &[[u8; 2]] {
+        &self.counts
+    }
+
+    /// Returns the cells in which `player` could complete a line.
+    pub fn threats(&self, player: Player) -> Bits {
+        self.threats[side(player)].cells
+    }
+
+    /// Returns the empty cells in which `player` would make a fork:
+    /// moves that don't complete a line, but after which `player` could
+    /// complete a line in two or more cells, at least one of them new,
+    /// so that the opponent can't block them all.
+    pub fn forks(&self, player: Player) -> Bits {
+        let (p, q) = (side(player), side(!player));
+        let k = self.geometry.k as u8;
+        let threats = self.threats(player);
+        let empty = self.empty();
+        // the moves that would make a new threat, the new threat the first
+        // of them was found to make, and the moves that would make two
+        let mut made = Bits::EMPTY;
+        let mut first = [0u8; MAX_CELLS];
+        let mut several = Bits::EMPTY;
+        for (line, count) in self.counts.iter().enumerate() {
+            if count[q] == 0 && count[p] == k - 2 {
+                let missing = self.geometry.lines[line] & empty;
+                let a = missing.first().unwrap();
+                let b = (missing ^ Bits::single(a)).first().unwrap();
+                for (cell, threat) in [(a, b), (b, a)] {
+                    if threats.contains(cell) || threats.contains(threat) {
+                        continue;
+                    }
+                    if !made.contains(cell) {
+                        made.set(cell);
+                        first[cell] = threat as u8;
+                    } else if first[cell] as usize != threat {
+                        several.set(cell);
+                    }
+                }
+            }
+        }
+        // with a threat already, any new one is enough
+        if threats.is_empty() {
+            several
+        } else {
+            made
+        }
+    }
+
+    /// Returns the 'obvious move' in the position, as a cell index,
+    /// along with the side who wins with this move or would win if
+    /// not for this move. See [`crate::mech::Game::undoubted`].
+    pub fn undoubted(&self) -> Option<(Player, usize)> {
+        [self.turn, !self.turn]
+            .into_iter()
+            .find_map(|player| Some((player, self.threats(player).first()?)))
+    }
+
+    /// Returns the position with the given marks and side to move on
+    /// the same grid, recomputing everything that's otherwise updated
+    /// incrementally.
+    pub(crate) fn with_marks(&self, marks: [Bits; 2], turn: Player) -> Self {
+        let geometry = &self.geometry;
+        let k = geometry.k as u8;
+        let mut pos = Self {
+            geometry: Arc::clone(geometry),
+            marks,
+            turn,
+            winner: None,
+            hash: if turn == Player::O {
+                geometry.turn_key
+            } else {
+                0
+            },
+            counts: vec![[0; 2]; geometry.lines.len()],
+            threats: [
+                Threats::new(geometry.cells()),
+                Threats::new(geometry.cells()),
+            ],
+        };
+        for (p, player) in [(0, Player::X), (1, Player::O)] {
+            for cell in marks[p] {
+                pos.hash ^= geometry.keys[cell][p];
+            }
+            for (line, &mask) in geometry.lines.iter().enumerate() {
+                let count = (mask & marks[p]).count() as u8;
+                pos.counts[line][p] = count;
+                if (mask & marks[1 - p]).is_empty() {
+                    if count == k {
+                        pos.winner = Some(player);
+                    } else if count == k - 1 {
+                        pos.threats[p].add((mask ^ (mask & marks[p])).first().unwrap());
+                    }
+                }
+            }
+        }
+        pos
+    }
+
+    /// Returns `true` if `player` has completed a line.
+    pub(crate) fn has_line(&self, player: Player) -> bool {
+        let k = self.geometry.k as u8;
+        self.counts.iter().any(|count| count[side(player)] == k)
+    }
+
+    /// Returns the position with the grid transformed.
+    /// **Panics** if the transform doesn't apply to the grid.
+    pub fn transformed(&self, t: Transform) -> Self {
+        let (rows, cols) = (self.geometry.rows, self.geometry.cols);
+        self.with_marks(
+            self.marks.map(|bits| t.apply_bits(bits, rows, cols)),
+            self.turn,
+        )
+    }
+
+    /// Returns the transform that maps the position onto its canonical
+    /// form. See [`symmetry::canonical_transform`].
+    pub fn canonical_transform(&self) -> Transform {
+        let (rows, cols) = (self.geometry.rows, self.geometry.cols);
+        symmetry::canonical_transform(self.marks[0], self.marks[1], rows, cols)
+    }
+
+    /// Returns the canonical form of the position, along with the
+    /// transform that maps the position onto it.
+    pub fn canonical(&self) -> (Self, Transform) {
+        let t = self.canonical_transform();
+        (self.transformed(t), t)
+    }
+
+    /// Returns the transforms that map the position onto itself
+    /// (always including [`Transform::Identity`]). Moves that these
+    /// transforms map onto each other lead to equivalent positions.
+    pub fn symmetries(&self) -> Vec<Transform> {
+        let (rows, cols) = (self.geometry.rows, self.geometry.cols);
+        symmetry::transforms(rows, cols)
+            .filter(|t| self.marks.map(|bits| t.apply_bits(bits, rows, cols)) == self.marks)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that everything `make` and `unmake` keep up to date
+    /// matches a position computed from scratch.
+    fn assert_matches_recompute(pos: &Position) {
+        let fresh = pos.with_marks([pos.marks(Player::X), pos.marks(Player::O)], pos.turn());
+        assert_eq!(pos.threats(Player::X), fresh.threats(Player::X));
+        assert_eq!(pos.threats(Player::O), fresh.threats(Player::O));
+        assert_eq!(pos.state(), fresh.state());
+        assert_eq!(pos.hash(), fresh.hash());
+        assert_eq!(pos.undoubted(), fresh.undoubted());
+        assert_eq!(pos.line_counts(), fresh.line_counts());
+    }
+
+    /// Returns the empty cells in which a player with `marks` could
+    /// complete a line, by checking every line.
+    fn brute_threats(geometry: &Geometry, marks: Bits, empty: Bits) -> Bits {
+        let mut threats = Bits::EMPTY;
+        for cell in empty {
+            let completes = |&line: &Bits| {
+                line.contains(cell) && (line & marks).count() == geometry.win_length() - 1
+            };
+            if geometry.lines().iter().any(completes) {
+                threats.set(cell);
+            }
+        }
+        threats
+    }
+
+    /// Returns the forks of `player` by playing every move that doesn't
+    /// complete a line, and counting the threats before and after it.
+    fn brute_forks(pos: &Position, player: Player) -> Bits {
+        let geometry = pos.geometry();
+        let marks = pos.marks(player);
+        let before = brute_threats(geometry, marks, pos.empty());
+        let mut forks = Bits::EMPTY;
+        for cell in pos.empty() {
+            if before.contains(cell) {
+                continue;
+            }
+            let mut empty = pos.empty();
+            empty.clear(cell);
+            let after = brute_threats(geometry, marks | Bits::single(cell), empty);
+            if after.count() >= 2 && !after.is_subset(before) {
+                forks.set(cell);
+            }
+        }
+        forks
+    }
+
+    #[test]
+    fn forks_match_brute_force() {
+        let mut rng = fastrand::Rng::with_seed(25);
+        let sizes = [(3, 3, 3), (4, 4, 3), (3, 5, 3), (5, 5, 4), (6, 7, 4)];
+        for game in 0..50 {
+            let (rows, cols, k) = sizes[game % sizes.len()];
+            let mut pos = Position::new(rows, cols, k);
+            while pos.state() == GameState::Ongoing {
+                for player in [Player::X, Player::O] {
+                    assert_eq!(pos.forks(player), brute_forks(&pos, player));
+                }
+                let empty = pos.empty();
+                pos.make(empty.iter().nth(rng.usize(0..empty.count())).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn make_and_unmake_match_recompute() {
+        let mut rng = fastrand::Rng::with_seed(5);
+        let sizes = [
+            (3, 3, 3),
+            (4, 4, 3),
+            (3, 5, 3),
+            (5, 5, 4),
+            (6, 7, 4),
+            (7, 7, 5),
+        ];
+        for game in 0..300 {
+            let (rows, cols, k) = sizes[game % sizes.len()];
+            let mut pos = Position::new(rows, cols, k);
+            let mut played = Vec::new();
+            while pos.state() == GameState::Ongoing {
+                let empty = pos.empty();
+                let cell = empty.iter().nth(rng.usize(0..empty.count())).unwrap();
+                pos.make(cell);
+                played.push(cell);
+                assert_matches_recompute(&pos);
+            }
+            while let Some(cell) = played.pop() {
+                pos.unmake(cell);
+                assert_matches_recompute(&pos);
+            }
+            assert_eq!(pos.hash(), Position::new(rows, cols, k).hash());
+        }
+    }
+}
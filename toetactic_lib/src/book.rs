@@ -0,0 +1,452 @@
+//! Opening books
+//!
+//! An opening book maps positions early in the game to the moves worth
+//! playing in them, weighted by how well they did, so that the engine
+//! can pick from proven moves instead of choosing at random between
+//! moves a depth-limited search can't tell apart. Positions are stored
+//! by the Zobrist hash of their canonical form (see
+//! [`Position::canonical`]), and moves are stored as cells of the
+//! canonical form, so every rotation and reflection of a position
+//! shares its entry.
+//!
+//! On disk, a book is the 4 bytes `TTOB`, a version byte (see
+//! [`OpeningBook::VERSION`]), the number of rows, columns and the win
+//! length as bytes, and the number of positions as a little-endian
+//! `u32`. Each position follows as its hash as a little-endian `u64`
+//! and its number of moves as a little-endian `u16`, and each move as
+//! its cell index as a byte and its weight as a little-endian `u32`.
+
+use crate::{
+    bitboard::{Position, MAX_CELLS},
+    mech::{Game, GameState, Move},
+    search::{self, EngineError, SearchLimits, SearchOptions},
+};
+use std::{
+    cmp,
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Formatter},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// Represents a move in an opening book. The more weight a move has,
+/// the more often it's picked.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub struct BookMove {
+    pub mv: Move,
+    pub weight: u32,
+}
+
+/// Represents the reasons an opening book could fail to load.
+#[derive(Debug)]
+pub enum BookError {
+    /// Reading the book failed. The tuple field gives the reason.
+    Io(io::Error),
+    /// The data doesn't start with `TTOB`.
+    InvalidMagic,
+    /// The book was written in a format this version of the library
+    /// can't read. The tuple field gives the format's version.
+    UnsupportedVersion(u8),
+    /// The grid is smaller than 3x3 or the win length is invalid.
+    InvalidSize { rows: usize, cols: usize, k: usize },
+    /// A move is outside the grid.
+    Corrupt,
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read the opening book: {err}"),
+            Self::InvalidMagic => write!(f, "not an opening book"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported opening book version {version}, expected {}",
+                OpeningBook::VERSION
+            ),
+            Self::InvalidSize { rows, cols, k } => write!(
+                f,
+                "invalid opening book for a {rows}x{cols} grid with win length {k}"
+            ),
+            Self::Corrupt => write!(f, "the opening book is corrupt"),
+        }
+    }
+}
+
+impl Error for BookError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BookError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A collection of weighted moves for positions on one grid size and
+/// win length. See the [module-level documentation](self).
+#[derive(Debug, Clone)]
+pub struct OpeningBook {
+    rows: usize,
+    cols: usize,
+    k: usize,
+    /// The moves for each position, as cells of its canonical form.
+    entries: HashMap<u64, Vec<(u8, u32)>>,
+}
+
+impl OpeningBook {
+    /// The version of the on-disk format.
+    pub const VERSION: u8 = 1;
+    const MAGIC: [u8; 4] = *b"TTOB";
+
+    /// Creates an empty book for a `rows` by `cols` grid where a player
+    /// needs `k` marks in a row to win.
+    /// **Panics** if the grid is smaller than 3x3, or if `k` is less than
+    /// 3 or longer than both sides of the grid.
+    pub fn new(rows: usize, cols: usize, k: usize) -> Self {
+        assert!(valid_size(rows, cols, k));
+        Self {
+            rows,
+            cols,
+            k,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Builds a book by having the engine play `games` games against
+    /// itself with the given limits and options, and adding the first
+    /// `plies` moves of each game with [`OpeningBook::add_game`]. Since
+    /// the engine picks at random between equally good moves, the games
+    /// take different paths. If the options have a seed, each game is
+    /// played with the seed plus the game's index, counting from 0.
+    /// **Panics** like [`OpeningBook::new`].
+    pub fn build(
+        rows: usize,
+        cols: usize,
+        k: usize,
+        games: usize,
+        plies: usize,
+        limits: SearchLimits,
+        options: &SearchOptions,
+    ) -> Result<Self, EngineError> {
+        let mut book = Self::new(rows, cols, k);
+        for i in 0..games {
+            let options = SearchOptions {
+                seed: options.seed.map(|seed| seed.wrapping_add(i as u64)),
+                ..options.clone()
+            };
+            let mut game = Game::with_win_length(rows, cols, k);
+            while game.state() == GameState::Ongoing {
+                let result = search::search(&game, limits, &options)?;
+                // the engine only ever picks an empty cell in an ongoing game
+                let _ = game.play(result.best_move);
+            }
+            book.add_game(&game, plies);
+        }
+        Ok(book)
+    }
+
+    /// Returns the number of rows of the grid.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the number of columns of the grid.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Returns the number of marks in a row needed to win.
+    pub fn win_length(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the number of positions in the book.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the book has no positions.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns `true` if the book is for the grid and win length of `pos`.
+    pub fn covers(&self, pos: &Position) -> bool {
+        let geometry = pos.geometry();
+        (geometry.rows(), geometry.cols(), geometry.win_length()) == (self.rows, self.cols, self.k)
+    }
+
+    /// Adds `weight` to the weight of playing `mv` in `pos`.
+    /// **Panics** if the book isn't for the grid and win length of
+    /// `pos`, or if `mv` is outside the grid.
+    pub fn add(&mut self, pos: &Position, mv: Move, weight: u32) {
+        assert!(self.covers(pos));
+        let (canonical, t) = pos.canonical();
+        let cell = canonical.geometry().transform(t, pos.geometry().index(mv)) as u8;
+        let moves = self.entries.entry(canonical.hash()).or_default();
+        match moves.iter_mut().find(|(c, _)| *c == cell) {
+            Some((_, w)) => *w = w.saturating_add(weight),
+            None => moves.push((cell, weight)),
+        }
+    }
+
+    /// Adds the first `plies` moves of a game that has ended, weighting
+    /// each move by how it turned out for the player who made it: 2 for
+    /// a win, 1 for a tie and 0 for a loss. Games that haven't ended are
+    /// ignored.
+    /// **Panics** if the book isn't for the grid and win length of the game.
+    pub fn add_game(&mut self, game: &Game, plies: usize) {
+        let state = game.state();
+        if state == GameState::Ongoing {
+            return;
+        }
+        let mut game = game.clone();
+        let mut moves = Vec::new();
+        while let Some(mv) = game.undo() {
+            moves.push(mv);
+        }
+        for mv in moves.into_iter().rev().take(plies) {
+            let weight = match state {
+                GameState::Decisive(winner) if winner == game.turn() => 2,
+                GameState::Decisive(_) => 0,
+                _ => 1,
+            };
+            self.add(game.position(), mv, weight);
+            // the moves were played in this game before
+            let _ = game.play(mv);
+        }
+    }
+
+    /// Returns the moves in the book for `pos`, from the highest weight
+    /// to the lowest, or no moves if the book has no entry for it or
+    /// isn't for its grid and win length.
+    pub fn moves(&self, pos: &Position) -> Vec<BookMove> {
+        if !self.covers(pos) {
+            return Vec::new();
+        }
+        let (canonical, t) = pos.canonical();
+        let Some(moves) = self.entries.get(&canonical.hash()) else {
+            return Vec::new();
+        };
+        let geometry = pos.geometry();
+        let mut moves: Vec<_> = moves
+            .iter()
+            .map(|&(cell, weight)| BookMove {
+                mv: geometry.to_move(geometry.transform(t.inverse(), cell.into())),
+                weight,
+            })
+            // a position whose hash matches by chance may have the cell taken
+            .filter(|book_move| pos.empty().contains(geometry.index(book_move.mv)))
+            .collect();
+        moves.sort_by_key(|book_move| cmp::Reverse(book_move.weight));
+        moves
+    }
+
+    /// Picks one of the moves in the book for `pos` at random, where
+    /// moves with more weight are more likely to be picked, or returns
+    /// `None` if there are no moves with any weight.
+    pub fn pick(&self, pos: &Position) -> Option<Move> {
+        self.pick_with(pos, &mut fastrand::Rng::new())
+    }
+
+    /// Like [`OpeningBook::pick`], but with the given random number
+    /// generator, so that the same generator state picks the same move.
+    pub fn pick_with(&self, pos: &Position, rng: &mut fastrand::Rng) -> Option<Move> {
+        let moves = self.moves(pos);
+        let total: u64 = moves.iter().map(|book_move| book_move.weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut r = rng.u64(0..total);
+        for book_move in moves {
+            if r < book_move.weight as u64 {
+                return Some(book_move.mv);
+            }
+            r -= book_move.weight as u64;
+        }
+        unreachable!()
+    }
+
+    /// Writes the book in its on-disk format.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&Self::MAGIC)?;
+        writer.write_all(&[
+            Self::VERSION,
+            self.rows as u8,
+            self.cols as u8,
+            self.k as u8,
+        ])?;
+        writer.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        // sorted, so that the same book is always written the same way
+        let mut hashes: Vec<_> = self.entries.keys().collect();
+        hashes.sort_unstable();
+        for hash in hashes {
+            let moves = &self.entries[hash];
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&(moves.len() as u16).to_le_bytes())?;
+            for &(cell, weight) in moves {
+                writer.write_all(&[cell])?;
+                writer.write_all(&weight.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a book written by [`OpeningBook::write_to`].
+    pub fn read_from(mut reader: impl Read) -> Result<Self, BookError> {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if header[..4] != Self::MAGIC {
+            return Err(BookError::InvalidMagic);
+        }
+        if header[4] != Self::VERSION {
+            return Err(BookError::UnsupportedVersion(header[4]));
+        }
+        let (rows, cols, k) = (header[5].into(), header[6].into(), header[7].into());
+        if !valid_size(rows, cols, k) {
+            return Err(BookError::InvalidSize { rows, cols, k });
+        }
+        let len = u32::from_le_bytes(header[8..].try_into().unwrap());
+        let mut book = Self::new(rows, cols, k);
+        for _ in 0..len {
+            let mut entry = [0; 10];
+            reader.read_exact(&mut entry)?;
+            let hash = u64::from_le_bytes(entry[..8].try_into().unwrap());
+            let count = u16::from_le_bytes(entry[8..].try_into().unwrap());
+            let mut moves = Vec::with_capacity(count.into());
+            for _ in 0..count {
+                let mut data = [0; 5];
+                reader.read_exact(&mut data)?;
+                if data[0] as usize >= rows * cols {
+                    return Err(BookError::Corrupt);
+                }
+                moves.push((data[0], u32::from_le_bytes(data[1..].try_into().unwrap())));
+            }
+            book.entries.insert(hash, moves);
+        }
+        Ok(book)
+    }
+
+    /// Saves the book to a file, in its on-disk format.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Loads a book saved by [`OpeningBook::save`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BookError> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Returns `true` if a book can be made for a `rows` by `cols` grid
+/// with win length `k`.
+fn valid_size(rows: usize, cols: usize, k: usize) -> bool {
+    rows > 2 && cols > 2 && rows * cols <= MAX_CELLS && k > 2 && k <= cmp::max(rows, cols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(moves: &[Move]) -> Game {
+        let mut game = Game::new(3, 3);
+        for &mv in moves {
+            game.play(mv).unwrap();
+        }
+        game
+    }
+
+    fn book() -> OpeningBook {
+        let mut book = OpeningBook::new(3, 3, 3);
+        book.add(game(&[]).position(), (1, 1), 3);
+        book.add(game(&[]).position(), (0, 0), 1);
+        book.add(game(&[(0, 0), (0, 1)]).position(), (2, 2), 5);
+        book
+    }
+
+    fn bytes(book: &OpeningBook) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        book.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn symmetric_positions_share_moves() {
+        let book = book();
+        // the same position rotated a quarter turn clockwise
+        let moves = book.moves(game(&[(0, 2), (1, 2)]).position());
+        assert_eq!(
+            moves,
+            [BookMove {
+                mv: (2, 0),
+                weight: 5
+            }]
+        );
+        let moves = book.moves(game(&[]).position());
+        assert_eq!(
+            moves[0],
+            BookMove {
+                mv: (1, 1),
+                weight: 3
+            }
+        );
+        assert!(book.moves(game(&[(1, 1)]).position()).is_empty());
+    }
+
+    #[test]
+    fn add_game_weights() {
+        let mut book = OpeningBook::new(3, 3, 3);
+        // X wins down the first column
+        let won = game(&[(0, 0), (1, 1), (1, 0), (2, 2), (2, 0)]);
+        book.add_game(&won, 2);
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.moves(game(&[]).position())[0].weight, 2);
+        assert_eq!(book.moves(game(&[(0, 0)]).position())[0].weight, 0);
+        assert_eq!(book.pick(game(&[(0, 0)]).position()), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let book = book();
+        let bytes = bytes(&book);
+        let read = OpeningBook::read_from(&bytes[..]).unwrap();
+        assert_eq!((read.rows(), read.cols(), read.win_length()), (3, 3, 3));
+        assert_eq!(read.len(), book.len());
+        assert_eq!(self::bytes(&read), bytes);
+        let pos = game(&[(0, 0), (0, 1)]);
+        assert_eq!(read.moves(pos.position()), book.moves(pos.position()));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let bytes = bytes(&book());
+        let read = |bytes: &[u8]| OpeningBook::read_from(bytes).unwrap_err();
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(matches!(read(&magic), BookError::InvalidMagic));
+        let mut version = bytes.clone();
+        version[4] = OpeningBook::VERSION + 1;
+        assert!(matches!(
+            read(&version),
+            BookError::UnsupportedVersion(v) if v == OpeningBook::VERSION + 1
+        ));
+        let mut size = bytes.clone();
+        size[7] = 4;
+        assert!(matches!(read(&size), BookError::InvalidSize { k: 4, .. }));
+        assert!(matches!(read(&bytes[..6]), BookError::Io(_)));
+        assert!(matches!(read(&bytes[..bytes.len() - 1]), BookError::Io(_)));
+        // the first move of the first position, outside the 3x3 grid
+        let mut cell = bytes.clone();
+        cell[22] = 9;
+        assert!(matches!(read(&cell), BookError::Corrupt));
+    }
+}
@@ -0,0 +1,396 @@
+//! Players that can be swapped for one another
+//!
+//! An [`Engine`] is anything that chooses moves: a search, a simple
+//! rule, or a person. The same code can then play any of them against
+//! any other.
+
+use crate::{
+    book::OpeningBook,
+    mcts::{self, MctsOptions},
+    mech::{Game, Move},
+    search::{self, EngineError, SearchLimits, SearchOptions},
+};
+use std::{cmp, sync::Arc};
+
+/// A player that chooses moves.
+pub trait Engine {
+    /// Returns the name of the player, for showing to people.
+    fn name(&self) -> &str;
+
+    /// Chooses a move in the position, returning an [`EngineError`] if
+    /// the game has already ended or no move could be chosen.
+    fn choose_move(&mut self, game: &Game) -> Result<Move, EngineError>;
+}
+
+/// Plays the best move found by alpha-beta minimax, or a move from an
+/// opening book when there's one for the position. Created with a seed
+/// in its options, it makes the same random choices in the same games:
+/// which book move to play, and which of several equally good moves.
+#[derive(Debug, Clone, Default)]
+pub struct Minimax {
+    pub limits: SearchLimits,
+    /// The options of the search. Their seed is only read when the
+    /// engine is created, and each search is given a new seed from it.
+    pub options: SearchOptions,
+    /// A book to play moves from before searching, which is only used
+    /// if it's for the same grid and win length.
+    pub book: Option<Arc<OpeningBook>>,
+    rng: fastrand::Rng,
+}
+
+impl Minimax {
+    /// Creates an engine that searches with the given limits and the
+    /// default options, without a book.
+    pub fn new(limits: SearchLimits) -> Self {
+        Self::with_options(limits, SearchOptions::default())
+    }
+
+    /// Creates an engine that searches with the given limits and
+    /// options, without a book.
+    pub fn with_options(limits: SearchLimits, options: SearchOptions) -> Self {
+        let rng = options
+            .seed
+            .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+        Self {
+            limits,
+            options,
+            book: None,
+            rng,
+        }
+    }
+}
+
+impl Engine for Minimax {
+    fn name(&self) -> &str {
+        "Minimax"
+    }
+
+    fn choose_move(&mut self, game: &Game) -> Result<Move, EngineError> {
+        search::max_depth(game, self.limits)?;
+        let book_move = self
+            .book
+            .as_ref()
+            .and_then(|book| book.pick_with(game.position(), &mut self.rng));
+        if let Some(mv) = book_move {
+            return Ok(mv);
+        }
+        let options = SearchOptions {
+            seed: Some(self.rng.u64(..)),
+            ..self.options.clone()
+        };
+        Ok(search::search(game, self.limits, &options)?.best_move)
+    }
+}
+
+/// Plays the move found by Monte Carlo tree search.
+#[derive(Debug, Clone, Default)]
+pub struct MonteCarlo {
+    pub options: MctsOptions,
+}
+
+impl MonteCarlo {
+    /// Creates an engine that searches with the given options.
+    pub fn new(options: MctsOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl Engine for MonteCarlo {
+    fn name(&self) -> &str {
+        "Monte Carlo"
+    }
+
+    fn choose_move(&mut self, game: &Game) -> Result<Move, EngineError> {
+        Ok(mcts::mcts(game, &self.options)?.best_move)
+    }
+}
+
+/// Plays at one of several levels of skill, from 1, which is easy to
+/// beat, to [`Skilled::MAX_LEVEL`], which always plays the best move it
+/// finds. Below the top level, it searches as many plies as its level
+/// at most, sometimes plays a random move instead (a blunder), and
+/// otherwise picks between moves at random, favouring better moves
+/// more strongly the higher its level. Created with a seed in its
+/// options, it makes the same random choices in the same games.
+#[derive(Debug, Clone)]
+pub struct Skilled {
+    /// Levels above [`Skilled::MAX_LEVEL`] are taken as the top level,
+    /// and `0` is taken as `1`.
+    pub level: u8,
+    pub limits: SearchLimits,
+    /// The options of the search. Their seed is only read when the
+    /// engine is created.
+    pub options: SearchOptions,
+    rng: fastrand::Rng,
+}
+
+impl Skilled {
+    /// The top level, which plays like [`Minimax`].
+    pub const MAX_LEVEL: u8 = 10;
+    /// The chance of a blunder at level 1, which shrinks towards 0 at
+    /// the top level.
+    const BLUNDER_RATE: f64 = 0.4;
+    /// How little better moves are favoured at level 1, which shrinks
+    /// towards 0 (always the best move) at the top level.
+    const TEMPERATURE: f64 = 0.5;
+    /// The evaluation, short of a forced win, at which a move is
+    /// considered about half as good as a win.
+    const EVAL_SCALE: f64 = 200.;
+
+    /// Creates an engine at the given level that searches with the
+    /// given limits and the default options.
+    pub fn new(level: u8, limits: SearchLimits) -> Self {
+        Self::with_options(level, limits, SearchOptions::default())
+    }
+
+    /// Creates an engine at the given level that searches with the
+    /// given limits and options.
+    pub fn with_options(level: u8, limits: SearchLimits, options: SearchOptions) -> Self {
+        let rng = options
+            .seed
+            .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+        Self {
+            level,
+            limits,
+            options,
+            rng,
+        }
+    }
+
+    /// Returns how far below the top level the engine is, from 0 (the
+    /// top level) to 1 (level 1).
+    fn weakness(&self) -> f64 {
+        (Self::MAX_LEVEL - self.level.clamp(1, Self::MAX_LEVEL)) as f64
+            / (Self::MAX_LEVEL - 1) as f64
+    }
+
+    /// Decides whether to blunder on this move.
+    fn blunders(&mut self) -> bool {
+        let weakness = self.weakness();
+        self.rng.f64() < Self::BLUNDER_RATE * weakness * weakness
+    }
+}
+
+impl Engine for Skilled {
+    fn name(&self) -> &str {
+        "Skilled"
+    }
+
+    fn choose_move(&mut self, game: &Game) -> Result<Move, EngineError> {
+        search::max_depth(game, self.limits)?;
+        if self.blunders() {
+            let empty = game.empty();
+            return Ok(empty[self.rng.usize(0..empty.len())]);
+        }
+        let weakness = self.weakness();
+        let mut limits = self.limits;
+        if weakness > 0. {
+            let depth = self.level.max(1) as usize;
+            limits.depth = Some(limits.depth.map_or(depth, |max| cmp::min(max, depth)));
+        }
+        let evals = search::analyze_with(game, limits, &self.options)?;
+        let temperature = Self::TEMPERATURE * weakness;
+        if temperature == 0. {
+            let best: Vec<_> = evals
+                .iter()
+                .filter(|eval| eval.score == evals[0].score)
+                .collect();
+            return Ok(best[self.rng.usize(0..best.len())].mv);
+        }
+        // how good each move is for the side to move, from -1 to 1
+        let sign = isize::from(game.turn()) as f64;
+        let utilities: Vec<_> = evals
+            .iter()
+            .map(|eval| match eval.forced_win() {
+                Some((winner, _)) if winner == game.turn() => 1.,
+                Some(_) => -1.,
+                None => (sign * eval.score as f64 / Self::EVAL_SCALE).tanh() / 2.,
+            })
+            .collect();
+        // evaluations are sorted best first, so the first is the largest
+        let weights: Vec<_> = utilities
+            .iter()
+            .map(|utility| ((utility - utilities[0]) / temperature).exp())
+            .collect();
+        let mut r = self.rng.f64() * weights.iter().sum::<f64>();
+        for (eval, weight) in evals.iter().zip(&weights) {
+            if r < *weight {
+                return Ok(eval.mv);
+            }
+            r -= weight;
+        }
+        Ok(evals[0].mv)
+    }
+}
+
+/// Plays a random empty cell.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Random {
+    rng: fastrand::Rng,
+}
+
+impl Random {
+    /// Creates an engine with a random seed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an engine with the given seed, so that it always plays
+    /// the same moves in the same games.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rng: fastrand::Rng::with_seed(seed),
+        }
+    }
+}
+
+impl Engine for Random {
+    fn name(&self) -> &str {
+        "Random"
+    }
+
+    fn choose_move(&mut self, game: &Game) -> Result<Move, EngineError> {
+        search::max_depth(game, SearchLimits::default())?;
+        let empty = game.empty();
+        Ok(empty[self.rng.usize(0..empty.len())])
+    }
+}
+
+/// Plays the obvious move when there is one (see
+/// [`Game::undoubted`]), and a random empty cell otherwise. It never
+/// misses a win in one move or a block of one, but sees nothing further.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Greedy {
+    random: Random,
+}
+
+impl Greedy {
+    /// Creates an engine with a random seed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an engine with the given seed, so that it always plays
+    /// the same moves in the same games.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            random: Random::with_seed(seed),
+        }
+    }
+}
+
+impl Engine for Greedy {
+    fn name(&self) -> &str {
+        "Greedy"
+    }
+
+    fn choose_move(&mut self, game: &Game) -> Result<Move, EngineError> {
+        search::max_depth(game, SearchLimits::default())?;
+        match game.undoubted() {
+            Some((_, mv)) => Ok(mv),
+            None => self.random.choose_move(game),
+        }
+    }
+}
+
+/// Plays the moves a person gives, which are asked for through a
+/// function that waits for them. Moves that can't be played are asked
+/// for again, and if the function returns `None`, the engine returns
+/// [`EngineError::NoMove`].
+#[derive(Debug, Clone)]
+pub struct Human<F> {
+    input: F,
+}
+
+impl<F: FnMut(&Game) -> Option<Move>> Human<F> {
+    /// Creates an engine that asks `input` for its moves.
+    pub fn new(input: F) -> Self {
+        Self { input }
+    }
+}
+
+impl<F: FnMut(&Game) -> Option<Move>> Engine for Human<F> {
+    fn name(&self) -> &str {
+        "Human"
+    }
+
+    fn choose_move(&mut self, game: &Game) -> Result<Move, EngineError> {
+        search::max_depth(game, SearchLimits::default())?;
+        loop {
+            let mv = (self.input)(game).ok_or(EngineError::NoMove)?;
+            if game.empty().contains(&mv) {
+                return Ok(mv);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mech::{GameState, Player};
+
+    /// Plays a game between `x` and `o`, returning it once it's over.
+    fn play(mut game: Game, x: &mut dyn Engine, o: &mut dyn Engine) -> Game {
+        while game.state() == GameState::Ongoing {
+            let mv = if game.turn() == Player::X {
+                x.choose_move(&game)
+            } else {
+                o.choose_move(&game)
+            };
+            game.play(mv.unwrap()).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn skilled_blunder_rate() {
+        let options = SearchOptions::deterministic(7);
+        let mut engine = Skilled::with_options(1, SearchLimits::default(), options);
+        let moves = 10_000;
+        let blunders = (0..moves).filter(|_| engine.blunders()).count();
+        let rate = blunders as f64 / moves as f64;
+        assert!((0.37..0.43).contains(&rate), "blunder rate {rate}");
+    }
+
+    #[test]
+    fn skilled_top_level_never_blunders() {
+        let mut engine = Skilled::with_options(
+            Skilled::MAX_LEVEL,
+            SearchLimits::default(),
+            SearchOptions::deterministic(7),
+        );
+        assert!((0..1000).all(|_| !engine.blunders()));
+    }
+
+    #[test]
+    fn seeded_games_repeat() {
+        let limits = SearchLimits::depth(3);
+        let history = |seed| {
+            let mut minimax = Minimax::with_options(limits, SearchOptions::deterministic(seed));
+            let mut skilled = Skilled::with_options(4, limits, SearchOptions::deterministic(seed));
+            let game = Game::with_win_length(4, 4, 3);
+            play(game, &mut minimax, &mut skilled).history().to_vec()
+        };
+        for seed in 0..5 {
+            assert_eq!(history(seed), history(seed));
+        }
+    }
+
+    #[test]
+    fn top_level_never_loses_to_the_bottom_level() {
+        let limits = SearchLimits::default();
+        for seed in 0..20 {
+            let options = SearchOptions::deterministic(seed);
+            let mut strong = Skilled::with_options(Skilled::MAX_LEVEL, limits, options.clone());
+            let mut weak = Skilled::with_options(1, limits, options);
+            // the top level plays X in half the games and O in the rest
+            let (game, strong_player) = if seed % 2 == 0 {
+                (play(Game::new(3, 3), &mut strong, &mut weak), Player::X)
+            } else {
+                (play(Game::new(3, 3), &mut weak, &mut strong), Player::O)
+            };
+            assert_ne!(game.state(), GameState::Decisive(!strong_player), "{game}");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Static evaluation of positions at the horizon of the search
+
+use crate::{bitboard::Position, mech::Player};
+
+/// A static evaluation function, which scores a position without
+/// searching it. The search uses it to score ongoing positions once
+/// it runs out of depth.
+pub trait Evaluate {
+    /// Returns the evaluation of `pos` from X's point of view, i.e.
+    /// positive if X is better and negative if O is better. Scores
+    /// are clamped to [`MAX_EVAL`] in either direction, so that they
+    /// can't be mistaken for forced wins.
+    fn evaluate(&self, pos: &Position) -> isize;
+}
+
+impl<F: Fn(&Position) -> isize> Evaluate for F {
+    fn evaluate(&self, pos: &Position) -> isize {
+        self(pos)
+    }
+}
+
+/// The largest evaluation, in either direction, that a static
+/// evaluation can give.
+pub const MAX_EVAL: isize = crate::search::WIN_SCORE / 2;
+
+/// Represents the weights of the features scored by [`Heuristic`].
+/// Each feature is scored for both players, and O's score is
+/// subtracted from X's.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Weights {
+    /// Per line the player has marked and the opponent hasn't.
+    pub open_line: isize,
+    /// Per open line that is two marks away from being completed
+    /// (two-in-a-rows when playing 4-in-a-row).
+    pub near_threat: isize,
+    /// Per open line that is one mark away from being completed
+    /// (three-in-a-rows when playing 4-in-a-row).
+    pub threat: isize,
+    /// Per mark, per step closer to the center of the grid than the
+    /// corners are.
+    pub center: isize,
+    /// If the player has two or more cells in which they could
+    /// complete a line, so the opponent can't block them all.
+    pub fork: isize,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            open_line: 1,
+            near_threat: 8,
+            threat: 40,
+            center: 1,
+            fork: 200,
+        }
+    }
+}
+
+/// A static evaluation that scores open lines, lines close to being
+/// completed, control of the center and forks, with configurable
+/// [`Weights`].
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub struct Heuristic {
+    pub weights: Weights,
+}
+
+impl Heuristic {
+    /// Creates a heuristic with the given weights.
+    pub fn new(weights: Weights) -> Self {
+        Self { weights }
+    }
+}
+
+impl Evaluate for Heuristic {
+    fn evaluate(&self, pos: &Position) -> isize {
+        let w = &self.weights;
+        let geometry = pos.geometry();
+        let k = geometry.win_length() as u8;
+        let mut score = 0;
+        for &[x, o] in pos.line_counts() {
+            let (marks, sign) = match (x, o) {
+                (0, 0) => continue,
+                (x, 0) => (x, 1),
+                (0, o) => (o, -1),
+                // blocked
+                _ => continue,
+            };
+            let mut line_score = w.open_line;
+            if marks == k - 1 {
+                line_score += w.threat;
+            } else if marks + 2 == k {
+                line_score += w.near_threat;
+            }
+            score += sign * line_score;
+        }
+        let (rows, cols) = (geometry.rows() as isize, geometry.cols() as isize);
+        for (player, sign) in [(Player::X, 1), (Player::O, -1)] {
+            for cell in pos.marks(player) {
+                let (r, c) = geometry.to_move(cell);
+                // distances are doubled so that the center is a whole number
+                let dist =
+                    (2 * r as isize - (rows - 1)).abs() + (2 * c as isize - (cols - 1)).abs();
+                score += sign * w.center * (rows - 1 + cols - 1 - dist) / 2;
+            }
+            if pos.threats(player).count() > 1 {
+                score += sign * w.fork;
+            }
+        }
+        score
+    }
+}
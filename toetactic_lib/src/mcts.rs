@@ -0,0 +1,398 @@
+//! Monte Carlo tree search
+//!
+//! Instead of searching every move to a fixed depth, Monte Carlo tree
+//! search plays many quick games to the end from the position (playouts)
+//! and grows a tree of the positions they pass through, sending more
+//! playouts down the moves whose playouts went best. The UCT formula
+//! decides between trying a promising move again and trying a move
+//! there's less known about. Since it doesn't need to look at every
+//! move to the same depth, it copes with large grids better than
+//! minimax does.
+//!
+//! Playouts aren't entirely random: a player who can complete a line
+//! always does, and otherwise blocks the opponent if they could
+//! complete one. Positions in the tree whose outcome is certain are
+//! solved like in minimax, so forced wins are reported the same way
+//! [`search`](crate::search::search) reports them.
+
+use crate::{
+    bitboard::Position,
+    eval::MAX_EVAL,
+    mech::{Game, GameState, Player},
+    search::{self, EngineError, SearchLimits, SearchResult, StopToken},
+    tablebase::Outcome,
+};
+use std::{
+    cmp,
+    time::{Duration, Instant},
+};
+
+/// Represents the settings of a Monte Carlo tree search. When several
+/// limits are set, the search stops at whichever it reaches first.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MctsOptions {
+    /// The number of playouts to run. `0` is taken as `1`.
+    pub playouts: u64,
+    /// The time to spend searching. At least one playout is always run.
+    pub time: Option<Duration>,
+    /// The exploration constant of the UCT formula. Higher values
+    /// spread the playouts more evenly between moves, and lower values
+    /// focus them on the moves that have done best so far. Defaults to
+    /// the square root of 2.
+    pub exploration: f64,
+    /// The seed of the random number generator. With a seed, the same
+    /// search always runs the same playouts and picks the same move,
+    /// unless it is stopped by a time limit or token. If `None`, a
+    /// random seed is used.
+    pub seed: Option<u64>,
+    /// A token for stopping the search early, like a time limit that
+    /// can be reached at any moment.
+    pub stop: Option<StopToken>,
+}
+
+impl Default for MctsOptions {
+    fn default() -> Self {
+        Self {
+            playouts: 10_000,
+            time: None,
+            exploration: std::f64::consts::SQRT_2,
+            seed: None,
+            stop: None,
+        }
+    }
+}
+
+/// Searches for the best move in the position with Monte Carlo tree
+/// search, returning an [`EngineError`] if the game has already ended.
+/// The best move is the one the most playouts went through, unless the
+/// position has been solved.
+///
+/// In the result, a score that isn't a forced win is the share of
+/// playouts through the best move that X won, counting ties as half,
+/// scaled from -[`MAX_EVAL`] (O won every playout) to [`MAX_EVAL`] (X
+/// won every playout). A forced win is found as soon as one move is
+/// shown to win, so it may not be the quickest win. `depth` is the
+/// depth of the deepest position in the tree, `seldepth` is the deepest
+/// ply a playout reached, `nodes` is the number of playouts run, and
+/// `cutoffs` is always 0.
+pub fn mcts(game: &Game, options: &MctsOptions) -> Result<SearchResult, EngineError> {
+    let start = Instant::now();
+    search::max_depth(game, SearchLimits::default())?;
+    let mut rng = options
+        .seed
+        .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+    let mut pos = game.position().clone();
+    let turn = pos.turn();
+    let mut tree = Tree {
+        nodes: vec![Node::new(usize::MAX, None)],
+        exploration: options.exploration,
+    };
+    let (mut playouts, mut depth, mut seldepth) = (0, 0, 0);
+    let mut path = Vec::new();
+    while playouts < cmp::max(options.playouts, 1) && tree.nodes[0].outcome.is_none() {
+        let stopped = options.stop.as_ref().is_some_and(StopToken::is_stopped)
+            || options.time.is_some_and(|time| start.elapsed() >= time);
+        if playouts > 0 && stopped {
+            break;
+        }
+        path.clear();
+        path.push(0);
+        loop {
+            let node = *path.last().unwrap();
+            if tree.nodes[node].outcome.is_some() {
+                break;
+            }
+            if !tree.nodes[node].expanded {
+                tree.expand(node, &mut pos);
+                if tree.nodes[node].outcome.is_some() {
+                    break;
+                }
+            }
+            let child = tree.select(node, pos.turn(), &mut rng);
+            pos.make(tree.nodes[child].cell);
+            path.push(child);
+            if tree.nodes[child].visits == 0 {
+                break;
+            }
+        }
+        let ply = path.len() - 1;
+        depth = cmp::max(depth, ply);
+        let winner = match tree.nodes[*path.last().unwrap()].outcome {
+            Some(Outcome::Win(_)) => Some(pos.turn()),
+            Some(Outcome::Loss(_)) => Some(!pos.turn()),
+            Some(Outcome::Tie) => None,
+            None => {
+                let (winner, plies) = playout(&mut pos, &mut rng);
+                seldepth = cmp::max(seldepth, ply + plies);
+                winner
+            }
+        };
+        let score = match winner {
+            Some(Player::X) => 1.,
+            Some(Player::O) => 0.,
+            None => 0.5,
+        };
+        for &node in path.iter().rev() {
+            let node = &mut tree.nodes[node];
+            node.visits += 1;
+            node.score += score;
+            if node.cell != usize::MAX {
+                pos.unmake(node.cell);
+            }
+        }
+        for &node in path.iter().rev() {
+            tree.solve(node);
+        }
+        playouts += 1;
+    }
+    let geometry = game.position().geometry();
+    let mut pv = Vec::new();
+    let mut node = 0;
+    while tree.nodes[node].expanded {
+        node = tree.best_child(node);
+        pv.push(geometry.to_move(tree.nodes[node].cell));
+    }
+    let root = &tree.nodes[0];
+    let best = &tree.nodes[tree.best_child(0)];
+    let score = match root.outcome {
+        Some(Outcome::Win(plies)) => search::win_score(turn, plies),
+        Some(Outcome::Loss(plies)) => search::win_score(!turn, plies),
+        Some(Outcome::Tie) => 0,
+        None if best.visits == 0 => 0,
+        None => ((2. * best.score / best.visits as f64 - 1.) * MAX_EVAL as f64).round() as isize,
+    };
+    Ok(SearchResult {
+        best_move: pv[0],
+        score,
+        pv,
+        depth: cmp::max(depth, 1),
+        seldepth: cmp::max(seldepth, depth),
+        nodes: playouts,
+        cutoffs: 0,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Represents a position in the search tree.
+struct Node {
+    /// The cell played to reach the position, or `usize::MAX` for the root.
+    cell: usize,
+    /// The index of the first child. Children are added together, so
+    /// they're next to each other in the tree.
+    first_child: usize,
+    children: usize,
+    expanded: bool,
+    visits: u32,
+    /// The total result of the playouts through the position, from X's
+    /// point of view: 1 per win and 0.5 per tie.
+    score: f64,
+    /// The outcome of the position with perfect play, from the side to
+    /// move's point of view, if it has been solved.
+    outcome: Option<Outcome>,
+}
+
+impl Node {
+    fn new(cell: usize, outcome: Option<Outcome>) -> Self {
+        Self {
+            cell,
+            first_child: 0,
+            children: 0,
+            expanded: false,
+            visits: 0,
+            score: 0.,
+            outcome,
+        }
+    }
+}
+
+/// Represents the search tree, with the root at index 0.
+struct Tree {
+    nodes: Vec<Node>,
+    exploration: f64,
+}
+
+impl Tree {
+    /// Adds a child to `node` for every legal move, where `pos` is the
+    /// position at `node`.
+    fn expand(&mut self, node: usize, pos: &mut Position) {
+        let first_child = self.nodes.len();
+        for cell in pos.empty() {
+            pos.make(cell);
+            let outcome = match pos.state() {
+                GameState::Decisive(_) => Some(Outcome::Loss(0)),
+                GameState::Tied => Some(Outcome::Tie),
+                GameState::Ongoing => None,
+            };
+            pos.unmake(cell);
+            self.nodes.push(Node::new(cell, outcome));
+        }
+        let children = self.nodes.len() - first_child;
+        let parent = &mut self.nodes[node];
+        parent.first_child = first_child;
+        parent.children = children;
+        parent.expanded = true;
+        self.solve(node);
+    }
+
+    /// Returns the child of `node` to send the next playout down, where
+    /// `turn` is the side to move at `node`, **assuming** `node` hasn't
+    /// been solved. Moves that have already been shown to lose are never
+    /// picked, and moves that haven't been tried are picked first.
+    fn select(&self, node: usize, turn: Player, rng: &mut fastrand::Rng) -> usize {
+        let node = &self.nodes[node];
+        let children = node.first_child..node.first_child + node.children;
+        let candidates: Vec<_> = children
+            .filter(|&child| !matches!(self.nodes[child].outcome, Some(Outcome::Win(_))))
+            .collect();
+        let untried: Vec<_> = candidates
+            .iter()
+            .copied()
+            .filter(|&child| self.nodes[child].visits == 0)
+            .collect();
+        if !untried.is_empty() {
+            return untried[rng.usize(0..untried.len())];
+        }
+        let log_visits = (node.visits as f64).ln();
+        let uct = |child: usize| {
+            let child = &self.nodes[child];
+            let visits = child.visits as f64;
+            let value = match turn {
+                Player::X => child.score / visits,
+                Player::O => 1. - child.score / visits,
+            };
+            value + self.exploration * (log_visits / visits).sqrt()
+        };
+        candidates
+            .into_iter()
+            .max_by(|&a, &b| uct(a).total_cmp(&uct(b)))
+            .unwrap()
+    }
+
+    /// Solves `node` if one of its moves is a forced win, or if every
+    /// move has been solved.
+    fn solve(&mut self, node: usize) {
+        let Node {
+            first_child,
+            children,
+            expanded,
+            outcome,
+            ..
+        } = self.nodes[node];
+        if !expanded || outcome.is_some() {
+            return;
+        }
+        let mut best: Option<Outcome> = None;
+        let mut solved = true;
+        for child in &self.nodes[first_child..first_child + children] {
+            match child.outcome {
+                Some(outcome) => {
+                    let outcome = outcome.before();
+                    if best.is_none_or(|best| outcome.rank() > best.rank()) {
+                        best = Some(outcome);
+                    }
+                }
+                None => solved = false,
+            }
+        }
+        if solved || matches!(best, Some(Outcome::Win(_))) {
+            self.nodes[node].outcome = best;
+        }
+    }
+
+    /// Returns the child of `node` with the best move, **assuming**
+    /// `node` has been expanded: the best solved move if `node` has
+    /// been solved, and otherwise the move the most playouts went
+    /// through, leaving out moves that have been shown to lose.
+    fn best_child(&self, node: usize) -> usize {
+        let node = &self.nodes[node];
+        let children = node.first_child..node.first_child + node.children;
+        match node.outcome {
+            Some(outcome) => children
+                .filter(|&child| {
+                    self.nodes[child]
+                        .outcome
+                        .is_some_and(|child| child.before() == outcome)
+                })
+                .max_by_key(|&child| self.nodes[child].visits)
+                .unwrap(),
+            None => children
+                .filter(|&child| !matches!(self.nodes[child].outcome, Some(Outcome::Win(_))))
+                .max_by_key(|&child| self.nodes[child].visits)
+                .unwrap(),
+        }
+    }
+}
+
+/// Plays a game to the end from `pos`, returning the winner and the
+/// number of plies played, and leaves `pos` as it was.
+fn playout(pos: &mut Position, rng: &mut fastrand::Rng) -> (Option<Player>, usize) {
+    let mut played = Vec::new();
+    while pos.state() == GameState::Ongoing {
+        let cell = match pos.undoubted() {
+            Some((_, cell)) => cell,
+            None => {
+                let empty = pos.empty();
+                empty.iter().nth(rng.usize(0..empty.count())).unwrap()
+            }
+        };
+        pos.make(cell);
+        played.push(cell);
+    }
+    let winner = match pos.state() {
+        GameState::Decisive(winner) => Some(winner),
+        _ => None,
+    };
+    for &cell in played.iter().rev() {
+        pos.unmake(cell);
+    }
+    (winner, played.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn best_move(notation: &str, seed: u64) -> SearchResult {
+        let options = MctsOptions {
+            playouts: 2000,
+            seed: Some(seed),
+            ..Default::default()
+        };
+        mcts(&notation.parse().unwrap(), &options).unwrap()
+    }
+
+    #[test]
+    fn finds_forced_moves() {
+        for seed in 0..5 {
+            let result = best_move("xx./oo./... x", seed);
+            assert_eq!(result.best_move, (0, 2));
+            assert_eq!(result.forced_win(), Some((Player::X, 1)));
+            // O has to block, even though X still wins after that
+            assert_eq!(best_move("xx./o../... o", seed).best_move, (0, 2));
+            // taking a corner lets X make two lines at once
+            let edges = [(0, 1), (1, 0), (1, 2), (2, 1)];
+            let result = best_move("x../.o./..x o", seed);
+            assert!(edges.contains(&result.best_move), "{seed}");
+        }
+    }
+
+    #[test]
+    fn seeded_searches_repeat() {
+        let search = || {
+            let options = MctsOptions {
+                playouts: 500,
+                seed: Some(3),
+                ..Default::default()
+            };
+            let result = mcts(&Game::with_win_length(5, 5, 4), &options).unwrap();
+            (
+                result.best_move,
+                result.score,
+                result.depth,
+                result.seldepth,
+                result.nodes,
+            )
+        };
+        assert_eq!(search(), search());
+    }
+}
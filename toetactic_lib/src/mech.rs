@@ -1,6 +1,14 @@
 //! Game mechanics
 
-use std::fmt::{self, Formatter};
+use crate::{
+    bitboard::{Bits, Position},
+    symmetry::{self, Transform},
+};
+use std::{
+    cmp,
+    error::Error,
+    fmt::{self, Formatter},
+};
 
 /// Represents the contents of a cell on the grid.
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -74,11 +82,11 @@ pub type GridData = Vec<Vec<Cell>>;
 pub struct Grid(GridData);
 
 impl Grid {
-    /// Creates an empty `n` by `n` square grid.
-    /// **Panics** if `n` < 3.
-    pub fn new(n: usize) -> Self {
-        assert!(n > 2);
-        Self(vec![vec![Cell::Empty; n]; n])
+    /// Creates an empty grid with `rows` rows and `cols` columns.
+    /// **Panics** if `rows` < 3 or `cols` < 3.
+    pub fn new(rows: usize, cols: usize) -> Self {
+        assert!(rows > 2 && cols > 2);
+        Self(vec![vec![Cell::Empty; cols]; rows])
     }
 
     /// Returns a reference to the grid data.
@@ -91,20 +99,67 @@ impl Grid {
         &mut self.0
     }
 
-    /// Returns the dimensions of the `n` by `n` grid.
-    pub fn n(&self) -> usize {
+    /// Returns the number of rows in the grid.
+    pub fn rows(&self) -> usize {
         self.0.len()
     }
+
+    /// Returns the number of columns in the grid.
+    pub fn cols(&self) -> usize {
+        self.0[0].len()
+    }
+
+    /// Returns `true` if the grid has as many rows as columns.
+    pub fn is_square(&self) -> bool {
+        self.rows() == self.cols()
+    }
+
+    /// Returns the grid with every cell moved by the transform.
+    /// **Panics** if the transform doesn't apply to the grid.
+    pub fn transformed(&self, t: Transform) -> Self {
+        let (rows, cols) = (self.rows(), self.cols());
+        let mut grid = Self::new(rows, cols);
+        for (i, row) in self.0.iter().enumerate() {
+            for (j, &cell) in row.iter().enumerate() {
+                let (r, c) = t.apply((i, j), rows, cols);
+                grid.0[r][c] = cell;
+            }
+        }
+        grid
+    }
+
+    /// Returns the canonical form of the grid, along with the transform
+    /// that maps the grid onto it. Grids that are rotations or
+    /// reflections of each other have the same canonical form.
+    /// See [`symmetry::canonical_transform`].
+    pub fn canonical(&self) -> (Self, Transform) {
+        let (mut x, mut o) = (Bits::EMPTY, Bits::EMPTY);
+        for (i, row) in self.0.iter().enumerate() {
+            for (j, &cell) in row.iter().enumerate() {
+                match cell {
+                    Cell::X => x.set(i * self.cols() + j),
+                    Cell::O => o.set(i * self.cols() + j),
+                    Cell::Empty => (),
+                }
+            }
+        }
+        let t = symmetry::canonical_transform(x, o, self.rows(), self.cols());
+        (self.transformed(t), t)
+    }
 }
 
 impl fmt::Display for Grid {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let n = self.n();
+        let (rows, cols) = (self.rows(), self.cols());
         let mut pp = String::new();
         pp.push_str(
             format!(
                 "┌{}┐\n",
-                "───┬".repeat(n).chars().take(4 * n - 1).collect::<String>()
+                "───┬"
+                    .repeat(cols)
+                    .chars()
+                    .take(4 * cols - 1)
+                    .collect::<String>()
             )
             .as_str(),
         );
@@ -121,15 +176,19 @@ impl fmt::Display for Grid {
                     )
                     .as_str(),
                 );
-                if j == n - 1 {
+                if j == cols - 1 {
                     pp.push_str("│\n");
                 }
             }
-            if i != n - 1 {
+            if i != rows - 1 {
                 pp.push_str(
                     format!(
                         "├{}┤\n",
-                        "───┼".repeat(n).chars().take(4 * n - 1).collect::<String>()
+                        "───┼"
+                            .repeat(cols)
+                            .chars()
+                            .take(4 * cols - 1)
+                            .collect::<String>()
                     )
                     .as_str(),
                 );
@@ -138,7 +197,11 @@ impl fmt::Display for Grid {
         pp.push_str(
             format!(
                 "└{}┘",
-                "───┴".repeat(n).chars().take(4 * n - 1).collect::<String>()
+                "───┴"
+                    .repeat(cols)
+                    .chars()
+                    .take(4 * cols - 1)
+                    .collect::<String>()
             )
             .as_str(),
         );
@@ -149,49 +212,207 @@ impl fmt::Display for Grid {
 /// Represents a move as a row index (first) and a column index (second) on a grid.
 pub type Move = (usize, usize);
 
+/// Represents the reasons a move could be rejected by [`Game::play`].
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum PlayError {
+    /// The game has already ended. The tuple field gives the final state.
+    GameOver(GameState),
+    /// The tuple field gives the row, which is outside the grid.
+    RowOutOfRange(usize),
+    /// The tuple field gives the column, which is outside the grid.
+    ColumnOutOfRange(usize),
+    /// The tuple field gives the cell, which isn't empty.
+    Occupied(Move),
+}
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GameOver(_) => write!(f, "the game is over"),
+            Self::RowOutOfRange(row) => write!(f, "row {row} is outside the grid"),
+            Self::ColumnOutOfRange(col) => write!(f, "column {col} is outside the grid"),
+            Self::Occupied(_) => write!(f, "that cell is already taken"),
+        }
+    }
+}
+
+impl Error for PlayError {}
+
+/// Represents the immediate threats of one player. See [`ThreatAnalysis`].
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct PlayerThreats {
+    /// The cells in which the player could complete a line.
+    pub wins: Vec<Move>,
+    /// The cells in which the player would make a fork: moves that
+    /// don't complete a line, but leave the player able to complete a
+    /// line in two or more cells, so the opponent can't block them all.
+    pub forks: Vec<Move>,
+}
+
+/// Represents every immediate threat in a position, as returned by
+/// [`Game::threats`]. All moves are in row-major order.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct ThreatAnalysis {
+    pub x: PlayerThreats,
+    pub o: PlayerThreats,
+    /// The cells the side to move has to play in to stop the opponent
+    /// completing a line on their next move. Empty if the side to move
+    /// can complete a line themself. If there's more than one, the
+    /// opponent can't be stopped.
+    pub blocks: Vec<Move>,
+}
+
+impl ThreatAnalysis {
+    /// Returns the threats of `player`.
+    pub fn player(&self, player: Player) -> &PlayerThreats {
+        match player {
+            Player::X => &self.x,
+            Player::O => &self.o,
+        }
+    }
+}
+
 /// Represents a game of Tic Tac Toe.
+///
+/// The game is backed by a bitboard [`Position`], which is what the
+/// engine searches; the grid, empty cells and obvious move are kept
+/// alongside it for convenience.
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Game {
     grid: Grid,
+    position: Position,
+    history: Vec<Move>,
     empty: Vec<Move>,
     undoubted: Option<(Player, Move)>,
-    state: GameState,
-    turn: Player,
 }
 
 impl Game {
-    /// Creates a new game with an `n` by `n` grid.
-    /// **Panics** if n < 3.
-    pub fn new(n: usize) -> Self {
-        assert!(n > 2);
+    /// Creates a new game with a `rows` by `cols` grid, where a
+    /// player must place as many marks in a row as the shorter
+    /// side of the grid to win (so on a square grid, an entire
+    /// row, column or main diagonal).
+    /// **Panics** if `rows` < 3 or `cols` < 3, or if the grid has
+    /// more than [`MAX_CELLS`](crate::bitboard::MAX_CELLS) cells.
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self::with_win_length(rows, cols, cmp::min(rows, cols))
+    }
+
+    /// Creates a new game with a `rows` by `cols` grid, where a player
+    /// wins by placing `k` of their marks in a row, horizontally,
+    /// vertically or diagonally (e.g. 4-in-a-row on a 7x7 grid).
+    /// **Panics** if `rows` < 3, `cols` < 3, `k` < 3 or `k` is longer
+    /// than both sides of the grid, or if the grid has more than
+    /// [`MAX_CELLS`](crate::bitboard::MAX_CELLS) cells.
+    pub fn with_win_length(rows: usize, cols: usize, k: usize) -> Self {
+        assert!(rows > 2 && cols > 2);
+        assert!(k > 2 && k <= cmp::max(rows, cols));
         let mut empty = Vec::new();
-        for i in 0..n {
-            for j in 0..n {
+        for i in 0..rows {
+            for j in 0..cols {
                 empty.push((i, j));
             }
         }
         Self {
-            grid: Grid::new(n),
+            grid: Grid::new(rows, cols),
+            position: Position::new(rows, cols, k),
+            history: Vec::new(),
             empty,
             undoubted: None,
-            state: GameState::Ongoing,
-            turn: Player::X,
         }
     }
 
+    /// Creates a game from a position and the moves that led to it.
+    pub(crate) fn from_position(position: Position, history: Vec<Move>) -> Self {
+        let geometry = position.geometry();
+        let mut grid = Grid::new(geometry.rows(), geometry.cols());
+        for (i, row) in grid.data_mut().iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = position.cell(geometry.index((i, j)));
+            }
+        }
+        let empty = position
+            .empty()
+            .iter()
+            .map(|cell| geometry.to_move(cell))
+            .collect();
+        let mut game = Self {
+            grid,
+            position,
+            history,
+            empty,
+            undoubted: None,
+        };
+        game.update_state();
+        game
+    }
+
+    /// Returns the game with the grid, and every move played so far,
+    /// moved by the transform.
+    /// **Panics** if the transform doesn't apply to the grid.
+    pub fn transformed(&self, t: Transform) -> Self {
+        let (rows, cols) = (self.grid.rows(), self.grid.cols());
+        Self::from_position(
+            self.position.transformed(t),
+            self.history
+                .iter()
+                .map(|&mv| t.apply(mv, rows, cols))
+                .collect(),
+        )
+    }
+
+    /// Returns the canonical form of the game, along with the transform
+    /// that maps the game onto it. Moves in the canonical game can be
+    /// mapped back with [`Transform::inverse`].
+    /// See [`symmetry::canonical_transform`].
+    pub fn canonical(&self) -> (Self, Transform) {
+        let t = self.position.canonical_transform();
+        (self.transformed(t), t)
+    }
+
     /// Returns a reference to the game grid.
     pub fn grid(&self) -> &Grid {
         &self.grid
     }
 
+    /// Returns a reference to the bitboard position backing the game.
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// Returns the number of marks a player needs in a row to win.
+    pub fn win_length(&self) -> usize {
+        self.position.geometry().win_length()
+    }
+
+    /// Returns the Zobrist hash of the position. Games with the same
+    /// grid size and win length, the same marks on the grid and the
+    /// same side to move have the same hash, regardless of the order
+    /// in which the moves were played.
+    pub fn hash(&self) -> u64 {
+        self.position.hash()
+    }
+
     /// Returns the player whose turn it is to move.
     pub fn turn(&self) -> Player {
-        self.turn
+        self.position.turn()
     }
 
     /// Returns the game state.
     pub fn state(&self) -> GameState {
-        self.state
+        self.position.state()
+    }
+
+    /// Returns the moves played so far, in the order
+    /// they were played. A game parsed from a string
+    /// starts out with an empty history.
+    pub fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    /// Returns the number of plies (moves by either player)
+    /// played so far, i.e. the number of marks on the grid.
+    pub fn ply(&self) -> usize {
+        self.position.occupied().count()
     }
 
     /// Returns the positions of the remaining empty
@@ -211,165 +432,258 @@ impl Game {
         self.undoubted
     }
 
+    /// Returns every immediate threat in the position: all the cells
+    /// in which either player could complete a line, all the cells the
+    /// side to move has to block, and all the forks either player could
+    /// make. Unlike [`Game::undoubted`], nothing is left out when there
+    /// are several threats. If the game has ended, there are no threats.
+    pub fn threats(&self) -> ThreatAnalysis {
+        if self.state() != GameState::Ongoing {
+            return ThreatAnalysis::default();
+        }
+        let pos = &self.position;
+        let geometry = pos.geometry();
+        let moves = |cells: Bits| cells.iter().map(|cell| geometry.to_move(cell)).collect();
+        let player = |player| PlayerThreats {
+            wins: moves(pos.threats(player)),
+            forks: moves(pos.forks(player)),
+        };
+        let turn = self.turn();
+        ThreatAnalysis {
+            x: player(Player::X),
+            o: player(Player::O),
+            blocks: if pos.threats(turn).is_empty() {
+                moves(pos.threats(!turn))
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
     /// Attempts to play `X` or `O` (depending on which
-    /// player's turn it is to move) in the given position.
-    /// This function returns `None` if the move was unsuccessful.
+    /// player's turn it is to move) in the given position,
+    /// and returns the game state after the move.
+    /// This function returns a [`PlayError`] if the move was
+    /// unsuccessful.
     /// Requirements:
     /// * The game must be ongoing
     /// * The position (`row`, `col`) must be within the grid,
     ///   and empty
-    pub fn play(&mut self, mv: Move) -> Option<()> {
+    pub fn play(&mut self, mv: Move) -> Result<GameState, PlayError> {
         let (row, col) = mv;
-        let n = self.grid.n();
-        if self.state != GameState::Ongoing
-            || row >= n
-            || col >= n
-            || self.grid.data()[row][col] != Cell::Empty
-        {
-            return None;
+        let state = self.state();
+        if state != GameState::Ongoing {
+            return Err(PlayError::GameOver(state));
+        }
+        if row >= self.grid.rows() {
+            return Err(PlayError::RowOutOfRange(row));
+        }
+        if col >= self.grid.cols() {
+            return Err(PlayError::ColumnOutOfRange(col));
+        }
+        if self.grid.data()[row][col] != Cell::Empty {
+            return Err(PlayError::Occupied(mv));
+        }
+        self.grid.data_mut()[row][col] = self.turn().into();
+        self.position.make(self.position.geometry().index(mv));
+        self.history.push(mv);
+        // `empty` is kept in row-major order
+        if let Ok(i) = self.empty.binary_search(&mv) {
+            self.empty.remove(i);
         }
-        self.grid.data_mut()[row][col] = self.turn.into();
-        self.turn = !self.turn;
         self.update_state();
-        Some(())
+        Ok(self.state())
+    }
+
+    /// Takes back the last move played, restoring the game
+    /// to exactly how it was before that move, and returns
+    /// the move that was taken back.
+    /// This function returns `None` if no moves have been played.
+    pub fn undo(&mut self) -> Option<Move> {
+        let mv @ (row, col) = self.history.pop()?;
+        self.grid.data_mut()[row][col] = Cell::Empty;
+        self.position.unmake(self.position.geometry().index(mv));
+        if let Err(i) = self.empty.binary_search(&mv) {
+            self.empty.insert(i, mv);
+        }
+        self.update_state();
+        Some(mv)
     }
 
     fn update_state(&mut self) {
-        self.undoubted = None;
+        let geometry = self.position.geometry();
+        self.undoubted = if self.state() == GameState::Ongoing {
+            self.position
+                .undoubted()
+                .map(|(player, cell)| (player, geometry.to_move(cell)))
+        } else {
+            None
+        };
+    }
+}
 
-        let n = self.grid.n();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the moves in order, alternating between X and O.
+    fn play(game: &mut Game, moves: &[Move]) {
+        for &mv in moves {
+            game.play(mv).unwrap();
+        }
+    }
 
-        let xw = n as isize;
-        let ow = -xw;
+    #[test]
+    fn four_in_a_row_off_main_diagonal() {
+        // X on the \ diagonal from (1, 2), with O taking the cell before it
+        let mut game = Game::with_win_length(7, 7, 4);
+        play(&mut game, &[(1, 2), (0, 1), (2, 3), (0, 0), (3, 4), (6, 0)]);
+        assert_eq!(game.state(), GameState::Ongoing);
+        assert_eq!(game.undoubted(), Some((Player::X, (4, 5))));
+        assert_eq!(game.play((4, 5)), Ok(GameState::Decisive(Player::X)));
+    }
 
-        let mut row_scores = Vec::new();
-        let mut cols = vec![Vec::new(); n];
-        let mut diags = vec![Vec::new(); 2];
+    #[test]
+    fn four_in_a_row_off_main_anti_diagonal() {
+        // X on the / diagonal from (2, 5), with O taking the cell before it
+        let mut game = Game::with_win_length(7, 7, 4);
+        play(&mut game, &[(2, 5), (1, 6), (3, 4), (0, 0), (4, 3), (6, 6)]);
+        assert_eq!(game.state(), GameState::Ongoing);
+        assert_eq!(game.undoubted(), Some((Player::X, (5, 2))));
+        assert_eq!(game.play((5, 2)), Ok(GameState::Decisive(Player::X)));
+    }
 
-        let mut empty = Vec::new();
-        let (mut xwin, mut owin) = (None, None);
+    #[test]
+    fn blocking_an_off_main_diagonal() {
+        // O has to block X's \ diagonal on a rectangular grid
+        let mut game = Game::with_win_length(5, 7, 4);
+        play(&mut game, &[(0, 3), (4, 0), (1, 4), (4, 1), (2, 5)]);
+        assert_eq!(game.undoubted(), Some((Player::X, (3, 6))));
+        play(&mut game, &[(3, 6)]);
+        assert_eq!(game.undoubted(), None);
+        assert_eq!(game.state(), GameState::Ongoing);
+    }
 
-        // check rows
-        for (i, row) in self.grid.data().iter().enumerate() {
-            let mut nempty = 0;
-            let mut empty_cell = None;
-            let row_score: isize = row
-                .iter()
-                .enumerate()
-                .map(|(j, &cell)| {
-                    let pos = (i, j);
-                    if cell == Cell::Empty {
-                        nempty += 1;
-                        empty_cell = Some(pos);
-                        empty.push(pos);
-                    }
-                    let cell_score: isize = cell.into();
-                    // it's in the \ diagonal
-                    if i == j {
-                        diags[0].push((cell_score, pos));
-                    }
-                    // it's in the / diagonal
-                    if i + j == n - 1 {
-                        diags[1].push((cell_score, pos));
-                    }
-                    cols[j].push((cell_score, pos));
-                    cell_score
-                })
-                .sum();
-            row_scores.push((nempty, row_score, empty_cell));
+    #[test]
+    fn undo_restores_every_ply() {
+        let mut game = Game::with_win_length(4, 5, 3);
+        let mut snapshots = vec![game.clone()];
+        for mv in [(1, 1), (0, 0), (1, 2), (2, 2), (3, 4), (0, 4), (1, 3)] {
+            game.play(mv).unwrap();
+            snapshots.push(game.clone());
         }
-        self.empty = empty;
-        for (nempty, row_score, empty_cell) in row_scores {
-            if row_score == xw {
-                return self.state = GameState::Decisive(Player::X);
-            } else if row_score == ow {
-                return self.state = GameState::Decisive(Player::O);
-            }
-            if nempty == 1 {
-                if row_score == xw - 1 {
-                    xwin = empty_cell
-                } else if row_score == ow + 1 {
-                    owin = empty_cell;
-                }
-            }
+        assert_eq!(game.state(), GameState::Decisive(Player::X));
+        assert_eq!(game.ply(), 7);
+        for snapshot in snapshots.iter().rev().skip(1) {
+            let mv = game.undo().unwrap();
+            assert_eq!(game, *snapshot);
+            assert_eq!(game.state(), snapshot.state());
+            assert_eq!(game.turn(), snapshot.turn());
+            assert_eq!(game.empty(), snapshot.empty());
+            assert_eq!(game.undoubted(), snapshot.undoubted());
+            assert_eq!(game.history(), snapshot.history());
+            assert!(game.empty().contains(&mv));
         }
-        // check columns
-        for col in cols {
-            let mut nempty = 0;
-            let mut empty_cell = None;
-            let col_score: isize = col
-                .into_iter()
-                .map(|(cell_score, pos)| {
-                    if cell_score == 0 {
-                        nempty += 1;
-                        empty_cell = Some(pos);
-                    }
-                    cell_score
-                })
-                .sum();
-            if col_score == xw {
-                return self.state = GameState::Decisive(Player::X);
-            }
-            if col_score == ow {
-                return self.state = GameState::Decisive(Player::O);
-            }
-            if nempty == 1 {
-                if col_score == xw - 1 {
-                    xwin = empty_cell;
-                } else if col_score == ow + 1 {
-                    owin = empty_cell;
-                }
-            }
+        assert_eq!(game.undo(), None);
+        assert_eq!(game, Game::with_win_length(4, 5, 3));
+    }
+
+    #[test]
+    fn undo_on_a_new_game() {
+        let mut game = Game::new(3, 3);
+        assert_eq!(game.undo(), None);
+        assert_eq!(game, Game::new(3, 3));
+    }
+
+    #[test]
+    fn canonical_moves_map_back() {
+        let mut game = Game::with_win_length(4, 5, 3);
+        play(&mut game, &[(0, 4), (1, 1)]);
+        let (canonical, t) = game.canonical();
+        assert_eq!(canonical.grid(), &game.grid().transformed(t));
+        for &mv in canonical.empty() {
+            let original = t.inverse().apply(mv, 4, 5);
+            let (mut a, mut b) = (canonical.clone(), game.clone());
+            a.play(mv).unwrap();
+            b.play(original).unwrap();
+            assert_eq!(a.canonical().0.hash(), b.canonical().0.hash());
         }
-        // check diagonals
-        for diag in diags {
-            let mut nempty = 0;
-            let mut empty_cell = None;
-            let diag_score: isize = diag
-                .into_iter()
-                .map(|(cell_score, pos)| {
-                    if cell_score == 0 {
-                        nempty += 1;
-                        empty_cell = Some(pos);
-                    }
-                    cell_score
-                })
-                .sum();
-            if diag_score == xw {
-                return self.state = GameState::Decisive(Player::X);
-            }
-            if diag_score == ow {
-                return self.state = GameState::Decisive(Player::O);
-            }
-            if nempty == 1 {
-                if diag_score == xw - 1 {
-                    xwin = empty_cell;
-                } else if diag_score == ow + 1 {
-                    owin = empty_cell;
-                }
-            }
+    }
+
+    #[test]
+    fn symmetric_games_share_canonical_form() {
+        let mut game = Game::new(3, 3);
+        play(&mut game, &[(0, 1), (2, 2)]);
+        let canonical = game.canonical().0;
+        for t in symmetry::transforms(3, 3) {
+            let (other, _) = game.transformed(t).canonical();
+            assert_eq!(other.grid(), canonical.grid());
+            assert_eq!(other.hash(), canonical.hash());
         }
-        // check if there are any obvious moves
-        let (xwin, owin) = (
-            xwin.map(|pos| (Player::X, pos)),
-            owin.map(|pos| (Player::O, pos)),
-        );
-        self.undoubted = if self.turn == Player::X && xwin.is_some() {
-            xwin
-        } else if self.turn == Player::O && owin.is_some() {
-            owin
-        } else if xwin.is_some() {
-            xwin
-        } else if owin.is_some() {
-            owin
-        } else {
-            None
-        };
+    }
 
-        self.state = if self.empty.is_empty() {
-            GameState::Tied
-        } else {
-            GameState::Ongoing
+    #[test]
+    fn rejected_moves_leave_the_game_unchanged() {
+        let mut game = Game::with_win_length(3, 4, 3);
+        play(&mut game, &[(1, 1), (0, 0)]);
+        let before = game.clone();
+        for (mv, err) in [
+            ((3, 0), PlayError::RowOutOfRange(3)),
+            ((0, 4), PlayError::ColumnOutOfRange(4)),
+            ((1, 1), PlayError::Occupied((1, 1))),
+            ((0, 0), PlayError::Occupied((0, 0))),
+        ] {
+            assert_eq!(game.play(mv), Err(err));
+            assert_eq!(game, before);
         }
     }
+
+    #[test]
+    fn no_moves_after_the_game_ends() {
+        let mut game = Game::new(3, 3);
+        play(&mut game, &[(0, 0), (1, 0), (0, 1), (1, 1), (0, 2)]);
+        let before = game.clone();
+        let over = PlayError::GameOver(GameState::Decisive(Player::X));
+        assert_eq!(game.play((2, 2)), Err(over));
+        assert_eq!(game, before);
+        // a tie
+        let mut game = Game::new(3, 3);
+        play(
+            &mut game,
+            &[
+                (0, 0),
+                (1, 1),
+                (2, 2),
+                (0, 1),
+                (2, 1),
+                (2, 0),
+                (0, 2),
+                (1, 2),
+                (1, 0),
+            ],
+        );
+        assert_eq!(game.state(), GameState::Tied);
+        assert_eq!(game.play((0, 0)), Err(PlayError::GameOver(GameState::Tied)));
+    }
+
+    #[test]
+    fn two_threats_to_block() {
+        // X can complete the top row or the left column, and O can't
+        // complete anything, but could make two threats at once
+        let game: Game = "xx./xo./..o o".parse().unwrap();
+        assert_eq!(
+            game.threats(),
+            ThreatAnalysis {
+                x: PlayerThreats {
+                    wins: vec![(0, 2), (2, 0)],
+                    forks: vec![],
+                },
+                o: PlayerThreats {
+                    wins: vec![],
+                    forks: vec![(0, 2), (2, 0)],
+                },
+                blocks: vec![(0, 2), (2, 0)],
+            }
+        );
+    }
 }
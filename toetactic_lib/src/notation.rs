@@ -0,0 +1,268 @@
+//! Text notation for positions
+//!
+//! A position is written as its rows from top to bottom, separated
+//! by `/`, where each cell is `x`, `o` or `.` (empty), followed by
+//! the side to move and, optionally, the win length. For example,
+//! `x.o/.x./..o x` is a 3x3 position with X to move. The win length
+//! may be left out when it's the length of the shorter side of the
+//! grid, which is the default for [`Game::new`].
+
+use crate::{
+    bitboard::{Bits, Position, MAX_CELLS},
+    mech::{Cell, Game, Player},
+};
+use std::{
+    cmp,
+    error::Error,
+    fmt::{self, Formatter},
+    str::FromStr,
+};
+
+/// Represents the reasons a position could fail to parse.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ParseGameError {
+    /// The grid or the side to move is missing.
+    MissingField,
+    /// There is something after the win length.
+    TrailingField(String),
+    /// A cell isn't `x`, `o` or `.`.
+    InvalidCell(char),
+    /// Not every row has the same number of cells.
+    RaggedRows,
+    /// The grid has fewer than 3 rows or columns, or too many cells.
+    InvalidSize { rows: usize, cols: usize },
+    /// The side to move isn't `x` or `o`.
+    InvalidTurn(String),
+    /// The win length isn't a number, is less than 3, or is longer
+    /// than both sides of the grid.
+    InvalidWinLength(String),
+    /// X must have as many marks as O, or one more.
+    PieceCount { x: usize, o: usize },
+    /// The side to move doesn't match the number of marks.
+    WrongTurn(Player),
+    /// Both players have completed a line.
+    TwoWinners,
+    /// The player who completed a line didn't make the last move.
+    InvalidWinner(Player),
+}
+
+impl fmt::Display for ParseGameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingField => write!(f, "expected a grid and a side to move"),
+            Self::TrailingField(s) => write!(f, "unexpected '{s}' after the win length"),
+            Self::InvalidCell(c) => write!(f, "invalid cell '{c}', expected 'x', 'o' or '.'"),
+            Self::RaggedRows => write!(f, "rows have different numbers of cells"),
+            Self::InvalidSize { rows, cols } => write!(
+                f,
+                "invalid grid size {rows}x{cols}, expected at least 3x3 and at most {MAX_CELLS} cells"
+            ),
+            Self::InvalidTurn(s) => write!(f, "invalid side to move '{s}', expected 'x' or 'o'"),
+            Self::InvalidWinLength(s) => write!(f, "invalid win length '{s}'"),
+            Self::PieceCount { x, o } => write!(
+                f,
+                "X has {x} marks and O has {o}, but X must have as many marks as O or one more"
+            ),
+            Self::WrongTurn(player) => {
+                write!(f, "{player:?} can't be to move with this number of marks")
+            }
+            Self::TwoWinners => write!(f, "both players have completed a line"),
+            Self::InvalidWinner(player) => write!(
+                f,
+                "{player:?} has completed a line but didn't make the last move"
+            ),
+        }
+    }
+}
+
+impl Error for ParseGameError {}
+
+impl FromStr for Game {
+    type Err = ParseGameError;
+
+    /// Parses a position from its notation. The game's history starts
+    /// out empty, so the moves that led to the position can't be undone.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let (grid, turn) = match (fields.next(), fields.next()) {
+            (Some(grid), Some(turn)) => (grid, turn),
+            _ => return Err(ParseGameError::MissingField),
+        };
+        let k = fields.next();
+        if let Some(field) = fields.next() {
+            return Err(ParseGameError::TrailingField(field.to_string()));
+        }
+
+        // grid
+        let mut data = Vec::new();
+        for row in grid.split('/') {
+            let row = row
+                .chars()
+                .map(|c| match c.to_ascii_lowercase() {
+                    'x' => Ok(Some(Player::X)),
+                    'o' => Ok(Some(Player::O)),
+                    '.' => Ok(None),
+                    _ => Err(ParseGameError::InvalidCell(c)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            data.push(row);
+        }
+        let (rows, cols) = (data.len(), data[0].len());
+        if data.iter().any(|row| row.len() != cols) {
+            return Err(ParseGameError::RaggedRows);
+        }
+        if rows < 3 || cols < 3 || rows * cols > MAX_CELLS {
+            return Err(ParseGameError::InvalidSize { rows, cols });
+        }
+
+        // side to move
+        let turn = match turn.to_ascii_lowercase().as_str() {
+            "x" => Player::X,
+            "o" => Player::O,
+            _ => return Err(ParseGameError::InvalidTurn(turn.to_string())),
+        };
+
+        // win length
+        let k = match k {
+            Some(k) => match k.parse() {
+                Ok(n) if n > 2 && n <= cmp::max(rows, cols) => n,
+                _ => return Err(ParseGameError::InvalidWinLength(k.to_string())),
+            },
+            None => cmp::min(rows, cols),
+        };
+
+        // validation
+        let (mut x, mut o) = (Bits::EMPTY, Bits::EMPTY);
+        for (i, row) in data.iter().enumerate() {
+            for (j, &cell) in row.iter().enumerate() {
+                match cell {
+                    Some(Player::X) => x.set(i * cols + j),
+                    Some(Player::O) => o.set(i * cols + j),
+                    None => (),
+                }
+            }
+        }
+        let (nx, no) = (x.count(), o.count());
+        let expected_turn = if nx == no {
+            Player::X
+        } else if nx == no + 1 {
+            Player::O
+        } else {
+            return Err(ParseGameError::PieceCount { x: nx, o: no });
+        };
+        if turn != expected_turn {
+            return Err(ParseGameError::WrongTurn(turn));
+        }
+        let position = Position::new(rows, cols, k).with_marks([x, o], turn);
+        match (position.has_line(Player::X), position.has_line(Player::O)) {
+            (true, true) => return Err(ParseGameError::TwoWinners),
+            // the winner's opponent would be to move
+            (true, false) if turn == Player::X => {
+                return Err(ParseGameError::InvalidWinner(Player::X))
+            }
+            (false, true) if turn == Player::O => {
+                return Err(ParseGameError::InvalidWinner(Player::O))
+            }
+            _ => (),
+        }
+        Ok(Game::from_position(position, Vec::new()))
+    }
+}
+
+impl fmt::Display for Game {
+    /// Writes the position in its notation, leaving out the win length
+    /// if it's the default.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let grid = self.grid();
+        let rows: Vec<String> = grid
+            .data()
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&cell| match cell {
+                        Cell::X => 'x',
+                        Cell::O => 'o',
+                        Cell::Empty => '.',
+                    })
+                    .collect()
+            })
+            .collect();
+        let turn = match self.turn() {
+            Player::X => 'x',
+            Player::O => 'o',
+        };
+        write!(f, "{} {turn}", rows.join("/"))?;
+        if self.win_length() != cmp::min(grid.rows(), grid.cols()) {
+            write!(f, " {}", self.win_length())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mech::GameState;
+
+    fn parse(s: &str) -> Result<Game, ParseGameError> {
+        s.parse()
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in [
+            "x.o/.x./..o x",
+            "xo./.x./... o",
+            "..../..../..../.... x",
+            "x..../.o.../..... x 4",
+            "x.o/.x./o.x o",
+        ] {
+            assert_eq!(parse(s).unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn round_trip_played_game() {
+        let mut game = Game::with_win_length(4, 6, 3);
+        for mv in [(0, 0), (1, 1), (3, 5), (2, 2), (0, 5)] {
+            game.play(mv).unwrap();
+        }
+        let parsed = parse(&game.to_string()).unwrap();
+        assert_eq!(parsed.grid(), game.grid());
+        assert_eq!(parsed.turn(), game.turn());
+        assert_eq!(parsed.win_length(), game.win_length());
+        assert_eq!(parsed.hash(), game.hash());
+    }
+
+    #[test]
+    fn case_and_finished_games() {
+        let game = parse("XXX/OO./... O").unwrap();
+        assert_eq!(game.to_string(), "xxx/oo./... o");
+        assert_eq!(game.state(), GameState::Decisive(Player::X));
+    }
+
+    #[test]
+    fn errors() {
+        use ParseGameError::*;
+        for (s, err) in [
+            ("", MissingField),
+            (".../.../...", MissingField),
+            (".../.../... x 3 y", TrailingField("y".into())),
+            ("..a/.../... x", InvalidCell('a')),
+            ("../.../... x", RaggedRows),
+            ("../../.. x", InvalidSize { rows: 3, cols: 2 }),
+            ("./././. x", InvalidSize { rows: 4, cols: 1 }),
+            (".../.../... z", InvalidTurn("z".into())),
+            (".../.../... x 4", InvalidWinLength("4".into())),
+            (".../.../... x 2", InvalidWinLength("2".into())),
+            (".../.../... x k", InvalidWinLength("k".into())),
+            ("xx./.../... o", PieceCount { x: 2, o: 0 }),
+            ("x../.../... x", WrongTurn(Player::X)),
+            ("xxx/ooo/... x", TwoWinners),
+            ("xxx/oo./o.. x", InvalidWinner(Player::X)),
+            ("ooo/xx./x.x o", InvalidWinner(Player::O)),
+        ] {
+            assert_eq!(parse(s).unwrap_err(), err, "{s:?}");
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Solving positions with proof-number search
+//!
+//! Proof-number search grows a tree of the positions after a move,
+//! always expanding the position that is cheapest to settle next: the
+//! one that would take the fewest positions to be won or lost to prove
+//! or disprove that a player can force a win. Unlike minimax, it gives
+//! an exact answer or none at all, and it can often prove a forced win
+//! on grids far too large to search to the end.
+//!
+//! A position where the side to move can complete a line, or where the
+//! opponent could complete two lines, is settled without being
+//! expanded, and if the opponent could complete one line, only the cell
+//! that blocks it is tried. Positions that have been settled are
+//! remembered by their hash, so that the same position reached by
+//! playing the same moves in a different order is only settled once.
+
+use crate::{
+    bitboard::Position,
+    mech::{Game, GameState, Move, Player},
+    search::{self, EngineError, SearchLimits, StopToken},
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Represents the outcome of a position with perfect play, from the
+/// point of view of the side to move.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Verdict {
+    Win,
+    Loss,
+    Draw,
+}
+
+/// Represents what a proof-number search found out about a position.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Proof {
+    /// The outcome of the position has been proven.
+    Proven(Verdict),
+    /// The search reached its limits before it could prove the outcome.
+    Unknown,
+}
+
+/// Represents the limits of a proof-number search. When several limits
+/// are set, the search stops at whichever it reaches first.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct ProofLimits {
+    /// The number of positions the tree may hold, which limits the
+    /// memory the search uses to about 40 bytes per position, plus the
+    /// positions it remembers as settled.
+    pub nodes: Option<usize>,
+    /// The time to spend searching.
+    pub time: Option<Duration>,
+    /// A token for stopping the search early, like a time limit that
+    /// can be reached at any moment.
+    pub stop: Option<StopToken>,
+}
+
+/// Represents the outcome of a proof-number search.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ProofResult {
+    pub proof: Proof,
+    /// A move that wins if the position is a proven win, or that holds
+    /// the draw if it's a proven draw.
+    pub best_move: Option<Move>,
+    /// The number of positions in the trees the search built. Proving
+    /// anything other than a win takes two trees: one to disprove that
+    /// the side to move wins, and one for the opponent.
+    pub tree_size: usize,
+    /// The time spent searching.
+    pub elapsed: Duration,
+}
+
+/// Tries to prove the outcome of the position with proof-number
+/// search, returning an [`EngineError`] if the game has already ended.
+pub fn prove(game: &Game, limits: &ProofLimits) -> Result<ProofResult, EngineError> {
+    let start = Instant::now();
+    search::max_depth(game, SearchLimits::default())?;
+    let mut pos = game.position().clone();
+    let turn = pos.turn();
+    let geometry = game.position().geometry();
+    let result = |proof, best_move: Option<usize>, tree_size| ProofResult {
+        proof,
+        best_move: best_move.map(|cell| geometry.to_move(cell)),
+        tree_size,
+        elapsed: start.elapsed(),
+    };
+    let mut prover = Prover::new(turn, limits, start);
+    let won = prover.run(&mut pos);
+    let mut tree_size = prover.nodes.len();
+    match won {
+        None => return Ok(result(Proof::Unknown, None, tree_size)),
+        Some(true) => {
+            let best = prover.solved_child(&pos);
+            return Ok(result(Proof::Proven(Verdict::Win), best, tree_size));
+        }
+        Some(false) => (),
+    }
+    let mut prover = Prover::new(!turn, limits, start);
+    let lost = prover.run(&mut pos);
+    tree_size += prover.nodes.len();
+    Ok(match lost {
+        None => result(Proof::Unknown, None, tree_size),
+        Some(true) => result(Proof::Proven(Verdict::Loss), None, tree_size),
+        Some(false) => {
+            let best = prover.solved_child(&pos);
+            result(Proof::Proven(Verdict::Draw), best, tree_size)
+        }
+    })
+}
+
+/// The proof or disproof number of a position that can't be proven or
+/// disproven.
+const INFINITY: u32 = u32::MAX;
+
+/// Represents a position in the tree.
+struct Node {
+    /// The cell played to reach the position, or `usize::MAX` for the root.
+    cell: usize,
+    /// The index of the first child. Children are added together, so
+    /// they're next to each other in the tree.
+    first_child: usize,
+    children: usize,
+    expanded: bool,
+    /// The number of positions that would have to be proven to prove
+    /// that the goal player wins, or 0 if they do.
+    proof: u32,
+    /// The number of positions that would have to be disproven to
+    /// prove that the goal player doesn't win, or 0 if they don't.
+    disproof: u32,
+}
+
+/// Proves or disproves that one player can force a win.
+struct Prover<'a> {
+    goal: Player,
+    limits: &'a ProofLimits,
+    start: Instant,
+    nodes: Vec<Node>,
+    /// The positions that have been settled, by hash, with `true` if
+    /// the goal player wins in them.
+    solved: HashMap<u64, bool>,
+}
+
+impl<'a> Prover<'a> {
+    fn new(goal: Player, limits: &'a ProofLimits, start: Instant) -> Self {
+        Self {
+            goal,
+            limits,
+            start,
+            nodes: Vec::new(),
+            solved: HashMap::new(),
+        }
+    }
+
+    /// Searches `pos` until it's settled or a limit is reached,
+    /// returning `Some(true)` if the goal player wins, `Some(false)` if
+    /// they don't, and `None` if the search reached its limits. `pos`
+    /// is left as it was.
+    fn run(&mut self, pos: &mut Position) -> Option<bool> {
+        let (proof, disproof) = self.evaluate(pos);
+        self.nodes.push(Node {
+            cell: usize::MAX,
+            first_child: 0,
+            children: 0,
+            expanded: false,
+            proof,
+            disproof,
+        });
+        let mut path = Vec::new();
+        while self.nodes[0].proof != 0 && self.nodes[0].disproof != 0 {
+            if self.out_of_budget() {
+                return None;
+            }
+            // find the most-proving position
+            path.clear();
+            path.push(0);
+            let mut node = 0;
+            while self.nodes[node].expanded {
+                let or_node = pos.turn() == self.goal;
+                let Node {
+                    first_child,
+                    children,
+                    ..
+                } = self.nodes[node];
+                node = (first_child..first_child + children)
+                    .min_by_key(|&child| {
+                        let child = &self.nodes[child];
+                        if or_node {
+                            child.proof
+                        } else {
+                            child.disproof
+                        }
+                    })
+                    .unwrap();
+                pos.make(self.nodes[node].cell);
+                path.push(node);
+            }
+            self.expand(node, pos);
+            for &node in path.iter().rev() {
+                self.update(node, pos);
+                if node != 0 {
+                    pos.unmake(self.nodes[node].cell);
+                }
+            }
+        }
+        Some(self.nodes[0].proof == 0)
+    }
+
+    /// Returns `true` if the search has reached one of its limits.
+    fn out_of_budget(&self) -> bool {
+        let limits = self.limits;
+        limits.nodes.is_some_and(|nodes| self.nodes.len() >= nodes)
+            || limits.time.is_some_and(|time| self.start.elapsed() >= time)
+            || limits.stop.as_ref().is_some_and(StopToken::is_stopped)
+    }
+
+    /// Returns the proof and disproof numbers of a position that hasn't
+    /// been expanded.
+    fn evaluate(&self, pos: &Position) -> (u32, u32) {
+        let turn = pos.turn();
+        let won = match pos.state() {
+            GameState::Decisive(winner) => Some(winner == self.goal),
+            GameState::Tied => Some(false),
+            GameState::Ongoing if !pos.threats(turn).is_empty() => Some(turn == self.goal),
+            // the opponent can't be stopped from completing a line
+            GameState::Ongoing if pos.threats(!turn).count() > 1 => Some(turn != self.goal),
+            GameState::Ongoing => self.solved.get(&pos.hash()).copied(),
+        };
+        match won {
+            Some(true) => (0, INFINITY),
+            Some(false) => (INFINITY, 0),
+            None => (1, 1),
+        }
+    }
+
+    /// Adds a child to `node` for every move worth trying, where `pos`
+    /// is the position at `node`, **assuming** `node` hasn't been settled.
+    fn expand(&mut self, node: usize, pos: &mut Position) {
+        let cells = match pos.threats(!pos.turn()).first() {
+            // any other move loses on the spot
+            Some(cell) => vec![cell],
+            None => search::representatives(pos),
+        };
+        let first_child = self.nodes.len();
+        for cell in cells {
+            pos.make(cell);
+            let (proof, disproof) = self.evaluate(pos);
+            pos.unmake(cell);
+            self.nodes.push(Node {
+                cell,
+                first_child: 0,
+                children: 0,
+                expanded: false,
+                proof,
+                disproof,
+            });
+        }
+        let children = self.nodes.len() - first_child;
+        let node = &mut self.nodes[node];
+        node.first_child = first_child;
+        node.children = children;
+        node.expanded = true;
+    }
+
+    /// Recomputes the proof and disproof numbers of `node` from its
+    /// children, where `pos` is the position at `node`, and remembers
+    /// the position if it has been settled.
+    fn update(&mut self, node: usize, pos: &Position) {
+        let Node {
+            first_child,
+            children,
+            expanded,
+            ..
+        } = self.nodes[node];
+        if !expanded {
+            return;
+        }
+        let children = &self.nodes[first_child..first_child + children];
+        let min = |f: fn(&Node) -> u32| children.iter().map(f).min().unwrap();
+        let sum = |f: fn(&Node) -> u32| {
+            children
+                .iter()
+                .map(f)
+                .fold(0, |total: u32, n| total.saturating_add(n))
+        };
+        let (proof, disproof) = if pos.turn() == self.goal {
+            (min(|child| child.proof), sum(|child| child.disproof))
+        } else {
+            (sum(|child| child.proof), min(|child| child.disproof))
+        };
+        if proof == 0 || disproof == 0 {
+            self.solved.insert(pos.hash(), proof == 0);
+        }
+        let node = &mut self.nodes[node];
+        node.proof = proof;
+        node.disproof = disproof;
+    }
+
+    /// Returns the cell of a move from the root that settles it the
+    /// same way the root was settled, where `pos` is the root.
+    fn solved_child(&self, pos: &Position) -> Option<usize> {
+        let root = &self.nodes[0];
+        if !root.expanded {
+            // settled on the spot, which is only by a line to complete
+            return pos.threats(pos.turn()).first();
+        }
+        let proven = root.proof == 0;
+        let mut children = root.first_child..root.first_child + root.children;
+        children
+            .find(|&child| {
+                let child = &self.nodes[child];
+                if proven {
+                    child.proof == 0
+                } else {
+                    child.disproof == 0
+                }
+            })
+            .map(|child| self.nodes[child].cell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prove(game: &Game) -> ProofResult {
+        super::prove(game, &ProofLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn three_by_three_is_a_draw() {
+        let game = Game::new(3, 3);
+        let result = prove(&game);
+        assert_eq!(result.proof, Proof::Proven(Verdict::Draw));
+        let mut after = game.clone();
+        after.play(result.best_move.unwrap()).unwrap();
+        assert_eq!(prove(&after).proof, Proof::Proven(Verdict::Draw));
+    }
+
+    #[test]
+    fn four_by_four_with_three_in_a_row_is_a_win() {
+        let game = Game::with_win_length(4, 4, 3);
+        let result = prove(&game);
+        assert_eq!(result.proof, Proof::Proven(Verdict::Win));
+        let mut after = game.clone();
+        after.play(result.best_move.unwrap()).unwrap();
+        assert_eq!(prove(&after).proof, Proof::Proven(Verdict::Loss));
+    }
+
+    #[test]
+    fn settled_positions() {
+        let win: Game = "xx./oo./... x".parse().unwrap();
+        let result = prove(&win);
+        assert_eq!(result.proof, Proof::Proven(Verdict::Win));
+        assert_eq!(result.best_move, Some((0, 2)));
+        let loss: Game = "xx./o../... o".parse().unwrap();
+        assert_eq!(prove(&loss).proof, Proof::Proven(Verdict::Loss));
+        let over: Game = "xxx/oo./... o".parse().unwrap();
+        assert!(matches!(
+            super::prove(&over, &ProofLimits::default()),
+            Err(EngineError::GameOver(_))
+        ));
+    }
+
+    #[test]
+    fn node_limit() {
+        let limits = ProofLimits {
+            nodes: Some(10),
+            ..Default::default()
+        };
+        let result = super::prove(&Game::with_win_length(6, 6, 4), &limits).unwrap();
+        assert_eq!(result.proof, Proof::Unknown);
+        assert_eq!(result.best_move, None);
+    }
+}
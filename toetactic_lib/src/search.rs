@@ -0,0 +1,1006 @@
+//! Searching for the best move
+
+use crate::{
+    bitboard::{Bits, Position, MAX_CELLS},
+    eval::{Evaluate, Heuristic, MAX_EVAL},
+    mech::{Game, GameState, Move, Player},
+    notation::ParseGameError,
+    tablebase::{Outcome, Tablebase},
+    tt::{Bound, Entry, TranspositionTable},
+};
+use std::{
+    cmp,
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Formatter},
+    sync::{
+        atomic::{AtomicBool, AtomicIsize, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Represents the limits of a search. When several limits are set,
+/// the search stops at whichever it reaches first.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub struct SearchLimits {
+    /// The number of plies to search, not the number of fullmoves.
+    /// If `None`, the search continues until the end of the game.
+    pub depth: Option<usize>,
+    /// The time to spend searching. The search deepens one ply at a
+    /// time, and when it runs out of time it returns the best move from
+    /// the last depth it completed.
+    pub time: Option<Duration>,
+    /// The number of positions to search, which works like `time`.
+    pub nodes: Option<u64>,
+}
+
+impl SearchLimits {
+    /// Returns limits that only limit the number of plies to search.
+    pub fn depth(depth: usize) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Default::default()
+        }
+    }
+
+    /// Returns limits that only limit the time to spend searching.
+    pub fn time(time: Duration) -> Self {
+        Self {
+            time: Some(time),
+            ..Default::default()
+        }
+    }
+
+    /// Returns limits that only limit the number of positions to search.
+    pub fn nodes(nodes: u64) -> Self {
+        Self {
+            nodes: Some(nodes),
+            ..Default::default()
+        }
+    }
+}
+
+/// A handle for stopping a search from another thread. Clones of a
+/// token share the same flag, so stopping one stops them all.
+#[derive(Debug, Clone, Default)]
+pub struct StopToken(Arc<AtomicBool>);
+
+impl StopToken {
+    /// Creates a token that hasn't been stopped.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops every search using this token. A search that is stopped
+    /// returns the best move from the last depth it completed.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the token has been stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl PartialEq for StopToken {
+    /// Two tokens are equal if they share the same flag.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for StopToken {}
+
+/// Represents the settings of a search, other than its limits.
+#[derive(Clone)]
+pub struct SearchOptions {
+    /// The static evaluation used to score positions at the horizon
+    /// of the search. Defaults to [`Heuristic`] with its default weights.
+    pub evaluator: Arc<dyn Evaluate + Send + Sync>,
+    /// A token for stopping the search early, like a time limit that
+    /// can be reached at any moment.
+    pub stop: Option<StopToken>,
+    /// The number of threads to search with, which share a
+    /// transposition table and split the moves in the position between
+    /// them. With a single thread (the default), the same search always
+    /// searches the same positions and finds the same scores, unless it
+    /// is stopped by a time limit or token. `0` is taken as `1`.
+    pub threads: usize,
+    /// A tablebase to look positions up in instead of searching them,
+    /// which is only used if it's for the same grid and win length.
+    pub tablebase: Option<Arc<Tablebase>>,
+    /// The seed of the random number generator used to pick between
+    /// equally good moves. If `None`, a random seed is used.
+    pub seed: Option<u64>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            evaluator: Arc::new(Heuristic::default()),
+            stop: None,
+            threads: 1,
+            tablebase: None,
+            seed: None,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Returns the default options with the given seed, so that the
+    /// same search always returns the same result, as long as it isn't
+    /// limited by time or stopped by a token.
+    pub fn deterministic(seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Debug for SearchOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SearchOptions")
+            .field("stop", &self.stop)
+            .field("threads", &self.threads)
+            .field("tablebase", &self.tablebase.is_some())
+            .field("seed", &self.seed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Represents the outcome of a search.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SearchResult {
+    pub best_move: Move,
+    /// The evaluation of the position, from X's point of view. A forced
+    /// win for X is scored as [`WIN_SCORE`] minus the number of plies
+    /// until the win, so that quicker wins score higher, and a forced
+    /// win for O is scored as the negative of that. Any other score is
+    /// a tie (`0`) or the static evaluation of the position the search
+    /// expects at its horizon, which is at most [`MAX_EVAL`] either way.
+    pub score: isize,
+    /// The principal variation: the line of play the engine expects,
+    /// starting with `best_move`. It may stop short of `depth` plies
+    /// if the game ends or part of it was cut off by the search.
+    pub pv: Vec<Move>,
+    /// The number of plies searched, which is the last depth the
+    /// search completed.
+    pub depth: usize,
+    /// The number of plies from the root of the deepest position
+    /// reached by the search.
+    pub seldepth: usize,
+    /// The number of positions searched.
+    pub nodes: u64,
+    /// The number of times alpha-beta pruning skipped the
+    /// remaining moves of a position.
+    pub cutoffs: u64,
+    /// The time spent searching.
+    pub elapsed: Duration,
+}
+
+impl SearchResult {
+    /// Returns the player who can force a win, and the number of
+    /// plies until they win, if the search found a forced win.
+    pub fn forced_win(&self) -> Option<(Player, usize)> {
+        forced_win(self.score)
+    }
+}
+
+/// The score of a win on the spot. See [`SearchResult::score`].
+pub const WIN_SCORE: isize = 1_000_000;
+
+/// Returns the score of `winner` winning `ply` plies from the root.
+pub(crate) fn win_score(winner: Player, ply: usize) -> isize {
+    isize::from(winner) * (WIN_SCORE - ply as isize)
+}
+
+/// Returns the player who can force a win, and the number of plies
+/// until they win, if `score` is the score of a forced win.
+fn forced_win(score: isize) -> Option<(Player, usize)> {
+    let plies = (WIN_SCORE - score.abs()) as usize;
+    if plies > MAX_CELLS {
+        None
+    } else if score > 0 {
+        Some((Player::X, plies))
+    } else {
+        Some((Player::O, plies))
+    }
+}
+
+/// Converts a score at `ply` plies from the root into one relative to
+/// the position itself, which is how it's stored in the transposition
+/// table, since the same position may be reached at different plies.
+fn score_to_tt(score: isize, ply: usize) -> i32 {
+    let score = match forced_win(score) {
+        Some((winner, plies)) => win_score(winner, plies - ply),
+        None => score,
+    };
+    score as i32
+}
+
+/// Converts a score stored in the transposition table into one at
+/// `ply` plies from the root.
+fn score_from_tt(score: i32, ply: usize) -> isize {
+    let score = score as isize;
+    match forced_win(score) {
+        Some((winner, plies)) => win_score(winner, plies + ply),
+        None => score,
+    }
+}
+
+/// Represents the reasons a search could fail.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum EngineError {
+    /// The game has already ended. The tuple field gives the final state.
+    GameOver(GameState),
+    /// The search depth is 0, so no moves would be searched.
+    ZeroDepth,
+    /// The position isn't valid. The tuple field gives the reason. Only
+    /// returned for positions given in text notation, since a [`Game`]
+    /// always holds a valid position.
+    InvalidPosition(ParseGameError),
+    /// A player who is asked for their moves didn't give one. See
+    /// [`crate::engine::Human`].
+    NoMove,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GameOver(_) => write!(f, "the game is over"),
+            Self::ZeroDepth => write!(f, "the search depth must be at least 1"),
+            Self::InvalidPosition(err) => write!(f, "invalid position: {err}"),
+            Self::NoMove => write!(f, "no move was given"),
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidPosition(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseGameError> for EngineError {
+    fn from(err: ParseGameError) -> Self {
+        Self::InvalidPosition(err)
+    }
+}
+
+/// Searches for the best move in the position, returning an
+/// [`EngineError`] instead of panicking if the game has already ended
+/// or the limits don't allow any search. When several moves are
+/// equally good, one of them is picked at random.
+///
+/// On grids with at most [`Tablebase::MAX_CELLS`] cells, the move is
+/// looked up in a tablebase instead, which is generated the first
+/// time a grid of that size and win length is searched; see
+/// [`Tablebase::shared`].
+pub fn try_best_move(game: &Game, limits: SearchLimits) -> Result<SearchResult, EngineError> {
+    let options = SearchOptions {
+        tablebase: Tablebase::shared(game.grid().rows(), game.grid().cols(), game.win_length()),
+        ..Default::default()
+    };
+    search(game, limits, &options)
+}
+
+/// Like [`try_best_move`], but for a position written in the text
+/// notation of [`crate::notation`], such as one supplied by a user.
+/// Returns [`EngineError::InvalidPosition`] if the notation can't be
+/// parsed or doesn't describe a position that can be reached.
+pub fn try_best_move_str(
+    notation: &str,
+    limits: SearchLimits,
+) -> Result<SearchResult, EngineError> {
+    let game: Game = notation.parse()?;
+    try_best_move(&game, limits)
+}
+
+/// Like [`try_best_move`], but with the given options.
+pub fn search(
+    game: &Game,
+    limits: SearchLimits,
+    options: &SearchOptions,
+) -> Result<SearchResult, EngineError> {
+    let start = Instant::now();
+    let maxdepth = max_depth(game, limits)?;
+    let mut rng = options
+        .seed
+        .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+    let pos = game.position();
+    let geometry = pos.geometry();
+    let result = |best: usize, score, depth| SearchResult {
+        best_move: geometry.to_move(best),
+        score,
+        pv: vec![geometry.to_move(best)],
+        depth,
+        seldepth: 0,
+        nodes: 0,
+        cutoffs: 0,
+        elapsed: start.elapsed(),
+    };
+    let root = match tablebase_root(options, pos) {
+        Some(root) => root,
+        None => {
+            let moves = match pos.undoubted() {
+                Some((player, cell)) if player == pos.turn() => {
+                    return Ok(result(cell, win_score(player, 1), 1));
+                }
+                // the only move that doesn't lose immediately
+                Some((_, cell)) => vec![cell],
+                None => representatives(pos),
+            };
+            search_root(pos, limits, options, maxdepth, moves, false)
+        }
+    };
+    let besteval = root.evals[0].1;
+    let best_moves: Vec<_> = root
+        .evals
+        .iter()
+        .filter(|&&(_, eval, _)| eval == besteval)
+        .collect();
+    let (best, score, pv) = best_moves[rng.usize(0..best_moves.len())];
+    Ok(SearchResult {
+        best_move: geometry.to_move(*best),
+        score: *score,
+        pv: pv.iter().map(|&cell| geometry.to_move(cell)).collect(),
+        depth: root.depth,
+        seldepth: root.seldepth,
+        nodes: root.nodes,
+        cutoffs: root.cutoffs,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Represents the evaluation of one move, as found by [`analyze`].
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct MoveEval {
+    pub mv: Move,
+    /// The evaluation of the position after the move, in the same
+    /// form as [`SearchResult::score`].
+    pub score: isize,
+    /// The line of play the engine expects, starting with `mv`.
+    pub pv: Vec<Move>,
+}
+
+impl MoveEval {
+    /// Returns the player who can force a win after the move, and the
+    /// number of plies until they win (counting the move itself), if
+    /// the search found a forced win.
+    pub fn forced_win(&self) -> Option<(Player, usize)> {
+        forced_win(self.score)
+    }
+}
+
+/// Evaluates every legal move in the position, returning them sorted
+/// from best to worst for the side to move, with moves that score the
+/// same in row-major order. Unlike [`try_best_move`], every move gets
+/// an exact score and line rather than just being shown to be worse
+/// than the best move, so the search takes longer.
+pub fn analyze(game: &Game, limits: SearchLimits) -> Result<Vec<MoveEval>, EngineError> {
+    analyze_with(game, limits, &SearchOptions::default())
+}
+
+/// Like [`analyze`], but with the given options.
+pub fn analyze_with(
+    game: &Game,
+    limits: SearchLimits,
+    options: &SearchOptions,
+) -> Result<Vec<MoveEval>, EngineError> {
+    let maxdepth = max_depth(game, limits)?;
+    let pos = game.position();
+    let geometry = pos.geometry();
+    let root = tablebase_root(options, pos)
+        .unwrap_or_else(|| search_root(pos, limits, options, maxdepth, representatives(pos), true));
+    Ok(root
+        .evals
+        .into_iter()
+        .map(|(cell, score, pv)| MoveEval {
+            mv: geometry.to_move(cell),
+            score,
+            pv: pv.into_iter().map(|cell| geometry.to_move(cell)).collect(),
+        })
+        .collect())
+}
+
+/// Returns the number of plies a search with `limits` may search to,
+/// or why it can't search at all.
+pub(crate) fn max_depth(game: &Game, limits: SearchLimits) -> Result<usize, EngineError> {
+    let state = game.state();
+    if state != GameState::Ongoing {
+        return Err(EngineError::GameOver(state));
+    }
+    match limits.depth {
+        Some(0) => Err(EngineError::ZeroDepth),
+        Some(depth) => Ok(cmp::min(depth, game.empty().len())),
+        None => Ok(game.empty().len()),
+    }
+}
+
+/// Returns the legal moves in the position, leaving out moves that
+/// are rotations or reflections of others, since they only need to be
+/// searched once.
+pub(crate) fn representatives(pos: &Position) -> Vec<usize> {
+    let symmetries = pos.symmetries();
+    pos.empty()
+        .iter()
+        .filter(|&cell| {
+            symmetries
+                .iter()
+                .all(|&t| pos.geometry().transform(t, cell) >= cell)
+        })
+        .collect()
+}
+
+/// Represents the outcome of the last iteration a search completed.
+struct Root {
+    depth: usize,
+    /// The score and principal variation of every legal move that is
+    /// a searched move or one of its rotations or reflections, sorted
+    /// from best to worst for the side to move. Unless every move was
+    /// searched exactly, only the scores of the best moves are exact.
+    evals: Vec<(usize, isize, Vec<usize>)>,
+    seldepth: usize,
+    nodes: u64,
+    cutoffs: u64,
+}
+
+/// Looks up every move in `pos` in the tablebase in `options`, if
+/// there is one for the position's grid and win length. The principal
+/// variations follow the first of the best moves in each position.
+fn tablebase_root(options: &SearchOptions, pos: &Position) -> Option<Root> {
+    let tablebase = options.tablebase.as_deref()?;
+    let player = pos.turn();
+    let mut evals: Vec<_> = tablebase
+        .probe_moves(pos)?
+        .into_iter()
+        .map(|(cell, outcome)| {
+            let score = match outcome {
+                Outcome::Win(plies) => win_score(player, plies),
+                Outcome::Tie => 0,
+                Outcome::Loss(plies) => win_score(!player, plies),
+            };
+            let mut pos = pos.clone();
+            let mut pv = vec![cell];
+            pos.make(cell);
+            while let Some((cells, _)) = tablebase.best_moves(&pos) {
+                pos.make(cells[0]);
+                pv.push(cells[0]);
+            }
+            (cell, score, pv)
+        })
+        .collect();
+    let sign = isize::from(player);
+    evals.sort_by_key(|&(_, eval, _)| -sign * eval);
+    Some(Root {
+        depth: pos.empty().count(),
+        evals,
+        seldepth: 0,
+        nodes: 0,
+        cutoffs: 0,
+    })
+}
+
+/// Searches `moves` from `pos` with iterative deepening. If `exact`,
+/// every move is searched with a full window, so that all of their
+/// scores are exact, and not just those of the best moves.
+fn search_root(
+    pos: &Position,
+    limits: SearchLimits,
+    options: &SearchOptions,
+    maxdepth: usize,
+    mut moves: Vec<usize>,
+    exact: bool,
+) -> Root {
+    let start = Instant::now();
+    let threads = cmp::max(options.threads, 1);
+    let shared = Shared {
+        tt: TranspositionTable::default(),
+        evaluator: &*options.evaluator,
+        deadline: limits.time.map(|time| start + time),
+        max_nodes: limits.nodes,
+        stop: options.stop.as_ref(),
+        nodes: AtomicU64::new(0),
+        stopped: AtomicBool::new(false),
+    };
+    let mut searchers: Vec<_> = (0..threads)
+        .map(|_| Searcher::new(pos.geometry().cells(), &shared))
+        .collect();
+    let symmetries = pos.symmetries();
+    let sign = isize::from(pos.turn());
+    let mut completed = None;
+    // unless the search may stop early, deepening gradually would only add work
+    let stoppable = limits.time.is_some() || limits.nodes.is_some() || options.stop.is_some();
+    let mindepth = if stoppable { 1 } else { maxdepth };
+    for depth in mindepth..=maxdepth {
+        // the threads take turns picking the next root move to search
+        let next = AtomicUsize::new(0);
+        // the best score so far, from the point of view of the side to move
+        let best = AtomicIsize::new(isize::MIN);
+        let evals = Mutex::new(HashMap::new());
+        // searches up to `count` root moves
+        let work = |searcher: &mut Searcher, count: usize| {
+            // the first iteration always completes, so that there is a move to return
+            searcher.stoppable = depth > mindepth;
+            let mut pos = pos.clone();
+            for _ in 0..count {
+                let Some(&cell) = moves.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                    break;
+                };
+                // moves that can't at least equal the best move are cut off,
+                // but moves that equal it are still told apart from worse ones
+                let (alpha, beta) = match best.load(Ordering::Relaxed) {
+                    _ if exact => (isize::MIN, isize::MAX),
+                    isize::MIN => (isize::MIN, isize::MAX),
+                    best if sign > 0 => (best - 1, isize::MAX),
+                    best => (isize::MIN, -best + 1),
+                };
+                pos.make(cell);
+                let eval = searcher.minimax(&mut pos, depth - 1, 1, alpha, beta);
+                pos.unmake(cell);
+                if shared.is_stopped() {
+                    break;
+                }
+                best.fetch_max(sign * eval, Ordering::Relaxed);
+                evals
+                    .lock()
+                    .unwrap()
+                    .insert(cell, (eval, searcher.pv[1].clone()));
+            }
+        };
+        let (main, helpers) = searchers.split_first_mut().unwrap();
+        // the first move is most likely the best, so it's searched on its
+        // own first, and the other moves can be cut off against its score
+        work(main, 1);
+        thread::scope(|scope| {
+            for searcher in helpers {
+                scope.spawn(|| work(searcher, usize::MAX));
+            }
+            work(main, usize::MAX);
+        });
+        if shared.is_stopped() {
+            break;
+        }
+        let evals = evals.into_inner().unwrap();
+        // the best moves of this iteration are searched first in the next one
+        moves.sort_by_key(|cell| -sign * evals[cell].0);
+        let mut all_evals: Vec<_> = pos
+            .empty()
+            .iter()
+            .filter_map(|cell| {
+                // the searched move that the move is a rotation or reflection of
+                let (rep, t) = symmetries
+                    .iter()
+                    .map(|&t| (pos.geometry().transform(t, cell), t))
+                    .find(|(rep, _)| evals.contains_key(rep))?;
+                let (eval, pv) = &evals[&rep];
+                // the line after a symmetric move is the same line, transformed back
+                let mut pv: Vec<_> = [rep]
+                    .iter()
+                    .chain(pv)
+                    .map(|&cell| pos.geometry().transform(t.inverse(), cell))
+                    .collect();
+                shared.complete_pv(pos, &mut pv, depth);
+                Some((cell, *eval, pv))
+            })
+            .collect();
+        all_evals.sort_by_key(|&(_, eval, _)| -sign * eval);
+        // a forced win found at this depth is already the quickest win
+        // (or the slowest loss), so searching deeper won't change it
+        let settled = if exact {
+            all_evals
+                .iter()
+                .all(|&(_, eval, _)| forced_win(eval).is_some())
+        } else {
+            forced_win(all_evals[0].1).is_some()
+        };
+        completed = Some(Root {
+            depth,
+            evals: all_evals,
+            seldepth: searchers.iter().map(|s| s.seldepth).max().unwrap_or(0),
+            nodes: searchers.iter().map(|s| s.nodes).sum(),
+            cutoffs: searchers.iter().map(|s| s.cutoffs).sum(),
+        });
+        if settled {
+            break;
+        }
+        // the next iteration would most likely run out of time
+        if limits.time.is_some_and(|time| start.elapsed() * 2 > time) {
+            break;
+        }
+    }
+    // the first iteration always completes
+    completed.unwrap()
+}
+
+/// Holds what the threads of a search share.
+struct Shared<'a> {
+    tt: TranspositionTable,
+    evaluator: &'a (dyn Evaluate + Sync),
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    stop: Option<&'a StopToken>,
+    /// The number of positions searched by every thread, which each
+    /// thread adds to every so often.
+    nodes: AtomicU64,
+    /// Whether the search has stopped, in which case the scores
+    /// being returned are meaningless and mustn't be used.
+    stopped: AtomicBool,
+}
+
+impl Shared<'_> {
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Extends a principal variation from `root` that was cut short by
+    /// the transposition table, by following the best moves stored in it.
+    fn complete_pv(&self, root: &Position, pv: &mut Vec<usize>, depth: usize) {
+        let mut pos = root.clone();
+        for &cell in pv.iter() {
+            pos.make(cell);
+        }
+        while pv.len() < depth && pos.state() == GameState::Ongoing {
+            match self.tt.probe(pos.hash()).and_then(|entry| entry.best) {
+                Some(cell) if pos.empty().contains(cell.into()) => {
+                    pos.make(cell.into());
+                    pv.push(cell.into());
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+/// Holds the state of one thread of a search.
+struct Searcher<'a> {
+    shared: &'a Shared<'a>,
+    /// The principal variation found at each ply, where the variation
+    /// at one ply is built from the move played and the variation at
+    /// the next ply.
+    pv: Vec<Vec<usize>>,
+    nodes: u64,
+    /// The number of positions searched that haven't been added to
+    /// the shared count yet.
+    unshared_nodes: u64,
+    cutoffs: u64,
+    seldepth: usize,
+    /// Whether the search may stop when it reaches its limits.
+    stoppable: bool,
+}
+
+impl<'a> Searcher<'a> {
+    fn new(cells: usize, shared: &'a Shared<'a>) -> Self {
+        Self {
+            shared,
+            pv: vec![Vec::new(); cells + 2],
+            nodes: 0,
+            unshared_nodes: 0,
+            cutoffs: 0,
+            seldepth: 0,
+            stoppable: false,
+        }
+    }
+
+    /// Returns `true` if the search has reached its time or node limit,
+    /// or has been stopped through its token. Only called every so
+    /// often, since checking the clock on every node would slow the
+    /// search down.
+    fn out_of_budget(&mut self) -> bool {
+        let shared = self.shared;
+        let nodes = shared
+            .nodes
+            .fetch_add(self.unshared_nodes, Ordering::Relaxed);
+        let nodes = nodes + self.unshared_nodes;
+        self.unshared_nodes = 0;
+        shared.max_nodes.is_some_and(|max| nodes >= max)
+            || shared
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            || shared.stop.is_some_and(StopToken::is_stopped)
+    }
+
+    /// Sets the principal variation at `ply` to `cell` followed by
+    /// the principal variation at the next ply.
+    fn update_pv(&mut self, ply: usize, cell: usize) {
+        let (head, tail) = self.pv.split_at_mut(ply + 1);
+        head[ply].clear();
+        head[ply].push(cell);
+        head[ply].extend_from_slice(&tail[0]);
+    }
+
+    fn minimax(
+        &mut self,
+        pos: &mut Position,
+        depth: usize,
+        ply: usize,
+        mut alpha: isize,
+        mut beta: isize,
+    ) -> isize {
+        self.nodes += 1;
+        self.unshared_nodes += 1;
+        if self.stoppable && self.unshared_nodes >= 1024 && self.out_of_budget() {
+            self.shared.stopped.store(true, Ordering::Relaxed);
+        }
+        if self.shared.is_stopped() {
+            return 0;
+        }
+        self.seldepth = cmp::max(self.seldepth, ply);
+        self.pv[ply].clear();
+        let state = pos.state();
+        if let GameState::Decisive(w) = state {
+            return win_score(w, ply);
+        }
+        if state == GameState::Tied {
+            return 0;
+        }
+        if let Some((player, cell)) = pos.undoubted().filter(|&(player, _)| player == pos.turn()) {
+            // an immediate win is certain, even at the horizon
+            self.pv[ply].push(cell);
+            return win_score(player, ply + 1);
+        }
+        if depth == 0 {
+            return self
+                .shared
+                .evaluator
+                .evaluate(pos)
+                .clamp(-MAX_EVAL, MAX_EVAL);
+        }
+        // positions reached by different move orders are only searched once
+        let key = pos.hash();
+        let depth8 = cmp::min(depth, u8::MAX as usize) as u8;
+        let entry = self.shared.tt.probe(key);
+        if let Some(entry) = entry.filter(|entry| entry.depth >= depth8) {
+            let score = score_from_tt(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower => alpha = cmp::max(alpha, score),
+                Bound::Upper => beta = cmp::min(beta, score),
+            }
+            if alpha >= beta {
+                self.cutoffs += 1;
+                return score;
+            }
+        }
+        let (alpha0, beta0) = (alpha, beta);
+        let turn = pos.turn();
+        let tiers = match pos.threats(!turn).first() {
+            // any other move lets the opponent complete a line
+            Some(block) => [Bits::single(block), Bits::EMPTY, Bits::EMPTY],
+            // forks are worth making or taking away from the opponent
+            None if depth > 1 => {
+                let own = pos.forks(turn);
+                let theirs = pos.forks(!turn) & !own;
+                [own, theirs, pos.empty() & !own & !theirs]
+            }
+            None => [Bits::EMPTY, Bits::EMPTY, pos.empty()],
+        };
+        // try the best move from an earlier search first
+        let ttbest = entry
+            .and_then(|entry| entry.best)
+            .map(usize::from)
+            .filter(|&cell| tiers.iter().any(|tier| tier.contains(cell)));
+        let moves = ttbest.into_iter().chain(
+            tiers
+                .into_iter()
+                .flat_map(|tier| tier.iter())
+                .filter(|&cell| Some(cell) != ttbest),
+        );
+        let mut best = None;
+        let eval = if pos.turn() == Player::X {
+            // maximizing player
+            let mut eval = isize::MIN;
+            for cell in moves {
+                pos.make(cell);
+                let score = self.minimax(pos, depth - 1, ply + 1, alpha, beta);
+                pos.unmake(cell);
+                if self.shared.is_stopped() {
+                    return 0;
+                }
+                if score > eval {
+                    eval = score;
+                    best = Some(cell);
+                    self.update_pv(ply, cell);
+                }
+                alpha = cmp::max(alpha, eval);
+                if beta <= alpha {
+                    self.cutoffs += 1;
+                    break;
+                }
+            }
+            eval
+        } else {
+            // minimizing player
+            let mut eval = isize::MAX;
+            for cell in moves {
+                pos.make(cell);
+                let score = self.minimax(pos, depth - 1, ply + 1, alpha, beta);
+                pos.unmake(cell);
+                if self.shared.is_stopped() {
+                    return 0;
+                }
+                if score < eval {
+                    eval = score;
+                    best = Some(cell);
+                    self.update_pv(ply, cell);
+                }
+                beta = cmp::min(beta, eval);
+                if alpha >= beta {
+                    self.cutoffs += 1;
+                    break;
+                }
+            }
+            eval
+        };
+        self.shared.tt.store(Entry {
+            key,
+            depth: depth8,
+            score: score_to_tt(eval, ply),
+            bound: if eval <= alpha0 {
+                Bound::Upper
+            } else if eval >= beta0 {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            },
+            best: best.map(|cell| cell as u8),
+        });
+        eval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn representative_moves(game: &Game) -> Vec<Move> {
+        let geometry = game.position().geometry();
+        representatives(game.position())
+            .into_iter()
+            .map(|cell| geometry.to_move(cell))
+            .collect()
+    }
+
+    #[test]
+    fn three_first_moves_on_an_empty_grid() {
+        // a corner, an edge and the centre
+        let moves = representative_moves(&Game::new(3, 3));
+        assert_eq!(moves, [(0, 0), (0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn representatives_on_a_rectangle() {
+        // only the reflections and the half turn apply, so each of the
+        // 4 classes of cells has a representative in the top-left corner
+        let moves = representative_moves(&Game::with_win_length(3, 4, 3));
+        assert_eq!(moves, [(0, 0), (0, 1), (1, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn representatives_of_a_symmetric_position() {
+        let game: Game = ".../.x./... o".parse().unwrap();
+        assert_eq!(representative_moves(&game), [(0, 0), (0, 1)]);
+        let game: Game = "x../.../... o".parse().unwrap();
+        // only the \ diagonal reflection leaves the position as it is
+        assert_eq!(representative_moves(&game).len(), 5);
+    }
+
+    #[test]
+    fn try_best_move_errors() {
+        let game = Game::new(3, 3);
+        assert_eq!(
+            try_best_move(&game, SearchLimits::depth(0)),
+            Err(EngineError::ZeroDepth)
+        );
+        let won: Game = "xxx/oo./... o".parse().unwrap();
+        assert_eq!(
+            try_best_move(&won, SearchLimits::default()),
+            Err(EngineError::GameOver(GameState::Decisive(Player::X)))
+        );
+        let tied: Game = "xox/xoo/oxx o".parse().unwrap();
+        assert_eq!(
+            try_best_move(&tied, SearchLimits::depth(3)),
+            Err(EngineError::GameOver(GameState::Tied))
+        );
+    }
+
+    #[test]
+    fn try_best_move_str_errors() {
+        assert_eq!(
+            try_best_move_str("xx./oo./.. x", SearchLimits::default()),
+            Err(EngineError::InvalidPosition(ParseGameError::RaggedRows))
+        );
+        assert_eq!(
+            try_best_move_str("xx./.../... x", SearchLimits::default()),
+            Err(EngineError::InvalidPosition(ParseGameError::PieceCount {
+                x: 2,
+                o: 0
+            }))
+        );
+        assert_eq!(
+            try_best_move_str("xxx/oo./... o", SearchLimits::default()),
+            Err(EngineError::GameOver(GameState::Decisive(Player::X)))
+        );
+        assert_eq!(
+            try_best_move_str("xx./oo./... x", SearchLimits::depth(0)),
+            Err(EngineError::ZeroDepth)
+        );
+        let result = try_best_move_str("xx./oo./... x", SearchLimits::default()).unwrap();
+        assert_eq!(result.best_move, (0, 2));
+        assert_eq!(result.forced_win(), Some((Player::X, 1)));
+    }
+
+    #[test]
+    fn forced_wins_match_the_tablebase() {
+        let mut rng = fastrand::Rng::with_seed(12);
+        for (rows, cols, k) in [(3, 3, 3), (3, 4, 3)] {
+            let tb = Tablebase::generate(rows, cols, k);
+            for _ in 0..5 {
+                let mut game = Game::with_win_length(rows, cols, k);
+                while game.state() == GameState::Ongoing {
+                    let result =
+                        search(&game, SearchLimits::default(), &SearchOptions::default()).unwrap();
+                    let expected = match tb.probe(game.position()).unwrap() {
+                        Outcome::Win(plies) => Some((game.turn(), plies)),
+                        Outcome::Tie => None,
+                        Outcome::Loss(plies) => Some((!game.turn(), plies)),
+                    };
+                    assert_eq!(result.forced_win(), expected, "{game}");
+                    let empty = game.empty();
+                    let mv = empty[rng.usize(0..empty.len())];
+                    game.play(mv).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn analyze_scores_every_move_exactly() {
+        let tb = Tablebase::generate(3, 3, 3);
+        // all but the first are symmetric, so only some moves are searched
+        for notation in [
+            "xx./o../... o",
+            "x../.o./..x o",
+            ".../.x./... o",
+            ".../.../... x",
+        ] {
+            let game: Game = notation.parse().unwrap();
+            let evals = analyze(&game, SearchLimits::default()).unwrap();
+            let mut moves: Vec<_> = evals.iter().map(|eval| eval.mv).collect();
+            moves.sort();
+            assert_eq!(&moves, game.empty(), "{notation}");
+            for eval in &evals {
+                let mut after = game.clone();
+                after.play(eval.mv).unwrap();
+                let expected = match tb.probe(after.position()) {
+                    None => Some((game.turn(), 1)),
+                    Some(Outcome::Win(plies)) => Some((after.turn(), plies + 1)),
+                    Some(Outcome::Tie) => None,
+                    Some(Outcome::Loss(plies)) => Some((game.turn(), plies + 1)),
+                };
+                assert_eq!(eval.forced_win(), expected, "{notation} {:?}", eval.mv);
+                assert_eq!(eval.pv[0], eval.mv);
+            }
+            let sign = isize::from(game.turn());
+            assert!(evals
+                .windows(2)
+                .all(|pair| (-sign * pair[0].score, pair[0].mv)
+                    < (-sign * pair[1].score, pair[1].mv)));
+        }
+    }
+
+    #[test]
+    fn try_best_move_uses_a_tablebase() {
+        // a search 1 ply deep can't see that X wins, but the tablebase can
+        let result = try_best_move_str("xx./o../... o", SearchLimits::depth(1)).unwrap();
+        assert_eq!(result.best_move, (0, 2));
+        assert_eq!(result.forced_win(), Some((Player::X, 4)));
+    }
+}
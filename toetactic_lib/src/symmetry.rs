@@ -0,0 +1,139 @@
+//! Symmetries of the grid
+
+use crate::{bitboard::Bits, mech::Move};
+
+/// Represents one of the symmetries of a grid, i.e. one of the 8
+/// rotations and reflections of a square (the dihedral group D4).
+/// Only [`Transform::Identity`], [`Transform::Rotate180`],
+/// [`Transform::FlipHorizontal`] and [`Transform::FlipVertical`]
+/// apply to grids that aren't square.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Transform {
+    Identity,
+    /// Rotates the grid 90 degrees clockwise.
+    Rotate90,
+    Rotate180,
+    /// Rotates the grid 270 degrees clockwise (90 degrees anticlockwise).
+    Rotate270,
+    /// Mirrors the grid left to right.
+    FlipHorizontal,
+    /// Mirrors the grid top to bottom.
+    FlipVertical,
+    /// Mirrors the grid along the \ diagonal.
+    Transpose,
+    /// Mirrors the grid along the / diagonal.
+    AntiTranspose,
+}
+
+impl Transform {
+    /// Every symmetry of a square grid.
+    pub const ALL: [Self; 8] = [
+        Self::Identity,
+        Self::Rotate90,
+        Self::Rotate180,
+        Self::Rotate270,
+        Self::FlipHorizontal,
+        Self::FlipVertical,
+        Self::Transpose,
+        Self::AntiTranspose,
+    ];
+
+    /// Returns `true` if the transform maps a `rows` by `cols` grid
+    /// onto itself.
+    pub fn applies_to(self, rows: usize, cols: usize) -> bool {
+        rows == cols
+            || matches!(
+                self,
+                Self::Identity | Self::Rotate180 | Self::FlipHorizontal | Self::FlipVertical
+            )
+    }
+
+    /// Returns the transform that undoes this one, which maps moves
+    /// on a transformed grid back to the original grid.
+    pub fn inverse(self) -> Self {
+        match self {
+            Self::Rotate90 => Self::Rotate270,
+            Self::Rotate270 => Self::Rotate90,
+            t => t,
+        }
+    }
+
+    /// Returns where `mv` ends up when a `rows` by `cols` grid is
+    /// transformed.
+    /// **Panics** if the transform doesn't apply to the grid.
+    pub fn apply(self, mv: Move, rows: usize, cols: usize) -> Move {
+        assert!(self.applies_to(rows, cols));
+        let (r, c) = mv;
+        let (lastr, lastc) = (rows - 1, cols - 1);
+        match self {
+            Self::Identity => (r, c),
+            Self::Rotate90 => (c, lastr - r),
+            Self::Rotate180 => (lastr - r, lastc - c),
+            Self::Rotate270 => (lastc - c, r),
+            Self::FlipHorizontal => (r, lastc - c),
+            Self::FlipVertical => (lastr - r, c),
+            Self::Transpose => (c, r),
+            Self::AntiTranspose => (lastc - c, lastr - r),
+        }
+    }
+
+    /// Returns the set of cells `bits` ends up as when a `rows` by
+    /// `cols` grid is transformed.
+    /// **Panics** if the transform doesn't apply to the grid.
+    pub fn apply_bits(self, bits: Bits, rows: usize, cols: usize) -> Bits {
+        let mut transformed = Bits::EMPTY;
+        for cell in bits {
+            let (r, c) = self.apply((cell / cols, cell % cols), rows, cols);
+            transformed.set(r * cols + c);
+        }
+        transformed
+    }
+}
+
+/// Returns every transform that applies to a `rows` by `cols` grid.
+pub fn transforms(rows: usize, cols: usize) -> impl Iterator<Item = Transform> {
+    Transform::ALL
+        .into_iter()
+        .filter(move |t| t.applies_to(rows, cols))
+}
+
+/// Returns the transform that maps a position, given by the cells
+/// marked by `X` and by `O`, onto its canonical form: the smallest
+/// of its symmetric forms. When several transforms give the canonical
+/// form, the one listed first in [`Transform::ALL`] is returned.
+pub fn canonical_transform(x: Bits, o: Bits, rows: usize, cols: usize) -> Transform {
+    transforms(rows, cols)
+        .min_by_key(|t| (t.apply_bits(x, rows, cols), t.apply_bits(o, rows, cols)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_undoes_transform() {
+        for (rows, cols) in [(3, 3), (4, 4), (3, 5), (6, 4)] {
+            for t in transforms(rows, cols) {
+                for mv in [(0, 0), (0, 1), (1, 2), (rows - 1, cols - 1)] {
+                    let moved = t.apply(mv, rows, cols);
+                    assert_eq!(t.inverse().apply(moved, rows, cols), mv, "{t:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rectangles_have_four_symmetries() {
+        assert_eq!(transforms(3, 3).count(), 8);
+        assert_eq!(
+            transforms(3, 5).collect::<Vec<_>>(),
+            [
+                Transform::Identity,
+                Transform::Rotate180,
+                Transform::FlipHorizontal,
+                Transform::FlipVertical,
+            ]
+        );
+    }
+}
@@ -0,0 +1,615 @@
+//! Tablebases of every position on small grids, solved by retrograde
+//! analysis
+//!
+//! A tablebase stores the outcome of every position that can be reached
+//! on a grid with a given win length, up to rotations and reflections,
+//! so that the best move in any of them can be looked up instead of
+//! searched. Positions are solved backwards, starting from the ones with
+//! the most marks, so that every move from a position leads to one that
+//! has already been solved.
+//!
+//! On disk, a tablebase is the 4 bytes `TTTB`, a version byte (see
+//! [`Tablebase::VERSION`]), the number of rows, columns and the win
+//! length as bytes, and the number of positions as a little-endian
+//! `u32`, followed by the positions sorted by key. Each position is its
+//! key as a little-endian `u32`, then its outcome as a signed byte:
+//! `d` if the side to move wins in `d` plies, `-d` if they lose in `d`
+//! plies, and `0` for a tie. The key of a position is its cells read as
+//! a base-3 number, with the first cell as the lowest digit, `0` for an
+//! empty cell, `1` for X and `2` for O, taking the smallest key among
+//! its rotations and reflections.
+
+use crate::{
+    bitboard::{Geometry, Position},
+    mech::{GameState, Player},
+    symmetry,
+};
+use std::{
+    error::Error,
+    fmt::{self, Formatter},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+/// Represents the outcome of a position with perfect play, from the
+/// point of view of the side to move. The tuple fields give the number
+/// of plies until the game ends.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum Outcome {
+    Win(usize),
+    Tie,
+    Loss(usize),
+}
+
+impl Outcome {
+    fn encode(self) -> i8 {
+        match self {
+            Self::Win(plies) => plies as i8,
+            Self::Tie => 0,
+            Self::Loss(plies) => -(plies as i8),
+        }
+    }
+
+    fn decode(value: i8) -> Self {
+        match value {
+            0 => Self::Tie,
+            plies if plies > 0 => Self::Win(plies as usize),
+            plies => Self::Loss(plies.unsigned_abs() as usize),
+        }
+    }
+
+    /// Returns the outcome for the opponent, one ply earlier, i.e. the
+    /// outcome of a move that leads to a position with this outcome.
+    pub fn before(self) -> Self {
+        match self {
+            Self::Win(plies) => Self::Loss(plies + 1),
+            Self::Tie => Self::Tie,
+            Self::Loss(plies) => Self::Win(plies + 1),
+        }
+    }
+
+    /// Ranks outcomes from the side to move's point of view: quicker
+    /// wins are better, and slower losses are better.
+    pub(crate) fn rank(self) -> isize {
+        match self {
+            Self::Win(plies) => isize::MAX - plies as isize,
+            Self::Tie => 0,
+            Self::Loss(plies) => isize::MIN + plies as isize,
+        }
+    }
+}
+
+/// Represents the reasons a tablebase could fail to load.
+#[derive(Debug)]
+pub enum TablebaseError {
+    /// Reading the tablebase failed. The tuple field gives the reason.
+    Io(io::Error),
+    /// The data doesn't start with `TTTB`.
+    InvalidMagic,
+    /// The tablebase was written in a format this version of the
+    /// library can't read. The tuple field gives the format's version.
+    UnsupportedVersion(u8),
+    /// The grid is smaller than 3x3, has more than
+    /// [`Tablebase::MAX_CELLS`] cells, or has an invalid win length.
+    InvalidSize { rows: usize, cols: usize, k: usize },
+    /// The positions aren't sorted by key, or an outcome is invalid.
+    Corrupt,
+}
+
+impl fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read the tablebase: {err}"),
+            Self::InvalidMagic => write!(f, "not a tablebase"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported tablebase version {version}, expected {}",
+                Tablebase::VERSION
+            ),
+            Self::InvalidSize { rows, cols, k } => {
+                write!(
+                    f,
+                    "invalid tablebase for a {rows}x{cols} grid with win length {k}"
+                )
+            }
+            Self::Corrupt => write!(f, "the tablebase is corrupt"),
+        }
+    }
+}
+
+impl Error for TablebaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TablebaseError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// The outcome of every ongoing position on a grid, up to rotations
+/// and reflections. See the [module-level documentation](self).
+#[derive(Debug, Clone)]
+pub struct Tablebase {
+    indexer: Indexer,
+    /// The keys of the positions, sorted.
+    keys: Vec<u32>,
+    outcomes: Vec<i8>,
+}
+
+impl Tablebase {
+    /// The largest number of cells a tablebase can be generated for.
+    pub const MAX_CELLS: usize = 16;
+    /// The version of the on-disk format.
+    pub const VERSION: u8 = 1;
+    const MAGIC: [u8; 4] = *b"TTTB";
+
+    /// Solves every position on a `rows` by `cols` grid where a player
+    /// needs `k` marks in a row to win.
+    /// **Panics** if the grid is smaller than 3x3 or has more than
+    /// [`Tablebase::MAX_CELLS`] cells, or if `k` is less than 3 or longer
+    /// than both sides of the grid.
+    pub fn generate(rows: usize, cols: usize, k: usize) -> Self {
+        assert!(valid_size(rows, cols, k));
+        let indexer = Indexer::new(Geometry::new(rows, cols, k));
+        let cells = indexer.geometry.cells();
+
+        // every ongoing position, by the number of marks on the grid
+        let mut layers = vec![vec![0]];
+        for ply in 0..cells - 1 {
+            let mut next = Vec::new();
+            for &key in &layers[ply] {
+                indexer.for_each_child(key, |child| {
+                    if let Child::Ongoing(child) = child {
+                        next.push(child);
+                    }
+                });
+            }
+            next.sort_unstable();
+            next.dedup();
+            layers.push(next);
+        }
+
+        // solved backwards, from the fullest positions
+        let mut solved: Vec<Vec<i8>> = vec![Vec::new(); layers.len()];
+        for ply in (0..layers.len()).rev() {
+            solved[ply] = layers[ply]
+                .iter()
+                .map(|&key| {
+                    let mut best = Outcome::Loss(0);
+                    indexer.for_each_child(key, |child| {
+                        let outcome = match child {
+                            Child::Won => Outcome::Win(1),
+                            Child::Tied => Outcome::Tie,
+                            Child::Ongoing(child) => {
+                                let i = layers[ply + 1].binary_search(&child).unwrap();
+                                Outcome::decode(solved[ply + 1][i]).before()
+                            }
+                        };
+                        if outcome.rank() > best.rank() {
+                            best = outcome;
+                        }
+                    });
+                    best.encode()
+                })
+                .collect();
+        }
+
+        let mut entries: Vec<_> = layers
+            .into_iter()
+            .flatten()
+            .zip(solved.into_iter().flatten())
+            .collect();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        let (keys, outcomes) = entries.into_iter().unzip();
+        Self {
+            indexer,
+            keys,
+            outcomes,
+        }
+    }
+
+    /// Returns the tablebase for a `rows` by `cols` grid with win length
+    /// `k`, generating it the first time it's asked for in the process
+    /// and sharing it after that. Returns `None` if a tablebase can't be
+    /// generated for the grid; see [`Tablebase::generate`].
+    pub fn shared(rows: usize, cols: usize, k: usize) -> Option<Arc<Self>> {
+        /// A tablebase's size and win length, and the tablebase once
+        /// it's been generated.
+        type Cached = ((usize, usize, usize), Arc<OnceLock<Arc<Tablebase>>>);
+        static TABLEBASES: Mutex<Vec<Cached>> = Mutex::new(Vec::new());
+        if !valid_size(rows, cols, k) {
+            return None;
+        }
+        // the lock is released before generating, so that grids of other
+        // sizes don't have to wait for it
+        let cached = {
+            let mut tablebases = TABLEBASES.lock().unwrap();
+            match tablebases.iter().find(|(size, _)| *size == (rows, cols, k)) {
+                Some((_, cached)) => Arc::clone(cached),
+                None => {
+                    let cached = Arc::new(OnceLock::new());
+                    tablebases.push(((rows, cols, k), Arc::clone(&cached)));
+                    cached
+                }
+            }
+        };
+        Some(Arc::clone(
+            cached.get_or_init(|| Arc::new(Self::generate(rows, cols, k))),
+        ))
+    }
+
+    /// Returns the number of rows of the grid.
+    pub fn rows(&self) -> usize {
+        self.indexer.geometry.rows()
+    }
+
+    /// Returns the number of columns of the grid.
+    pub fn cols(&self) -> usize {
+        self.indexer.geometry.cols()
+    }
+
+    /// Returns the number of marks in a row needed to win.
+    pub fn win_length(&self) -> usize {
+        self.indexer.geometry.win_length()
+    }
+
+    /// Returns the number of positions in the tablebase.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns `true` if the tablebase has no positions, which never
+    /// happens for a generated tablebase.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns `true` if the tablebase is for the grid and win length
+    /// of `pos`.
+    pub fn covers(&self, pos: &Position) -> bool {
+        *pos.geometry() == *self.indexer.geometry
+    }
+
+    /// Returns the outcome of `pos` with perfect play, or `None` if the
+    /// game has ended or the tablebase isn't for this grid and win length.
+    pub fn probe(&self, pos: &Position) -> Option<Outcome> {
+        if !self.covers(pos) || pos.state() != GameState::Ongoing {
+            return None;
+        }
+        let (x, o) = (pos.marks(Player::X), pos.marks(Player::O));
+        let key = self.indexer.key(|cell| {
+            if x.contains(cell) {
+                1
+            } else if o.contains(cell) {
+                2
+            } else {
+                0
+            }
+        });
+        let i = self.keys.binary_search(&key).ok()?;
+        Some(Outcome::decode(self.outcomes[i]))
+    }
+
+    /// Returns the outcome of every move in `pos` with perfect play, as
+    /// cell indices along with the outcome for the side making the move,
+    /// or `None` if [`Tablebase::probe`] would return `None`. It also
+    /// returns `None` if the position after one of the moves is missing
+    /// from the tablebase, which only happens if the tablebase was read
+    /// from a file that left it out.
+    pub fn probe_moves(&self, pos: &Position) -> Option<Vec<(usize, Outcome)>> {
+        self.probe(pos)?;
+        let mut pos = pos.clone();
+        pos.empty()
+            .iter()
+            .map(|cell| {
+                pos.make(cell);
+                let outcome = match pos.state() {
+                    GameState::Decisive(_) => Some(Outcome::Win(1)),
+                    GameState::Tied => Some(Outcome::Tie),
+                    GameState::Ongoing => self.probe(&pos).map(Outcome::before),
+                };
+                pos.unmake(cell);
+                Some((cell, outcome?))
+            })
+            .collect()
+    }
+
+    /// Returns the best moves in `pos` with perfect play, as cell
+    /// indices, along with their outcome for the side to move, or `None`
+    /// if [`Tablebase::probe`] would return `None`.
+    pub fn best_moves(&self, pos: &Position) -> Option<(Vec<usize>, Outcome)> {
+        let moves = self.probe_moves(pos)?;
+        let best = moves
+            .iter()
+            .map(|&(_, outcome)| outcome)
+            .max_by_key(|outcome| outcome.rank())?;
+        let cells = moves
+            .into_iter()
+            .filter(|&(_, outcome)| outcome == best)
+            .map(|(cell, _)| cell)
+            .collect();
+        Some((cells, best))
+    }
+
+    /// Writes the tablebase in its on-disk format.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&Self::MAGIC)?;
+        writer.write_all(&[
+            Self::VERSION,
+            self.rows() as u8,
+            self.cols() as u8,
+            self.win_length() as u8,
+        ])?;
+        writer.write_all(&(self.len() as u32).to_le_bytes())?;
+        for (key, &outcome) in self.keys.iter().zip(&self.outcomes) {
+            writer.write_all(&key.to_le_bytes())?;
+            writer.write_all(&outcome.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads a tablebase written by [`Tablebase::write_to`].
+    pub fn read_from(mut reader: impl Read) -> Result<Self, TablebaseError> {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if header[..4] != Self::MAGIC {
+            return Err(TablebaseError::InvalidMagic);
+        }
+        if header[4] != Self::VERSION {
+            return Err(TablebaseError::UnsupportedVersion(header[4]));
+        }
+        let (rows, cols, k) = (header[5].into(), header[6].into(), header[7].into());
+        if !valid_size(rows, cols, k) {
+            return Err(TablebaseError::InvalidSize { rows, cols, k });
+        }
+        let len = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() != len * 5 {
+            return Err(TablebaseError::Corrupt);
+        }
+        let cells = rows * cols;
+        let (mut keys, mut outcomes) = (Vec::with_capacity(len), Vec::with_capacity(len));
+        for entry in data.chunks_exact(5) {
+            let key = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            let outcome = i8::from_le_bytes([entry[4]]);
+            if keys.last().is_some_and(|&last| last >= key)
+                || outcome.unsigned_abs() as usize > cells
+            {
+                return Err(TablebaseError::Corrupt);
+            }
+            keys.push(key);
+            outcomes.push(outcome);
+        }
+        Ok(Self {
+            indexer: Indexer::new(Geometry::new(rows, cols, k)),
+            keys,
+            outcomes,
+        })
+    }
+
+    /// Saves the tablebase to a file, in its on-disk format.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Loads a tablebase saved by [`Tablebase::save`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TablebaseError> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Returns `true` if a tablebase can be made for a `rows` by `cols`
+/// grid with win length `k`.
+fn valid_size(rows: usize, cols: usize, k: usize) -> bool {
+    rows > 2 && cols > 2 && rows * cols <= Tablebase::MAX_CELLS && k > 2 && k <= rows.max(cols)
+}
+
+/// What a move leads to, while generating a tablebase.
+enum Child {
+    /// The player who made the move won.
+    Won,
+    Tied,
+    /// The game goes on. The tuple field gives the key of the position.
+    Ongoing(u32),
+}
+
+/// Computes the keys of positions. See the [module-level
+/// documentation](self).
+#[derive(Debug, Clone)]
+struct Indexer {
+    geometry: Arc<Geometry>,
+    /// For each symmetry of the grid, the value of each digit of the
+    /// key, by the cell it's in before the grid is transformed.
+    place_values: Vec<Vec<u32>>,
+    /// The lines, as masks of cells.
+    lines: Vec<u32>,
+}
+
+impl Indexer {
+    fn new(geometry: Geometry) -> Self {
+        let (rows, cols) = (geometry.rows(), geometry.cols());
+        let place_values = symmetry::transforms(rows, cols)
+            .map(|t| {
+                (0..geometry.cells())
+                    .map(|cell| 3u32.pow(geometry.transform(t, cell) as u32))
+                    .collect()
+            })
+            .collect();
+        let lines = geometry
+            .lines()
+            .iter()
+            .map(|line| line.iter().fold(0, |mask, cell| mask | 1 << cell))
+            .collect();
+        Self {
+            geometry: Arc::new(geometry),
+            place_values,
+            lines,
+        }
+    }
+
+    /// Returns the key of a position, given the digit of each cell.
+    fn key(&self, digit: impl Fn(usize) -> u32) -> u32 {
+        let digits: Vec<_> = (0..self.geometry.cells()).map(digit).collect();
+        self.place_values
+            .iter()
+            .map(|values| digits.iter().zip(values).map(|(d, v)| d * v).sum())
+            .min()
+            .unwrap()
+    }
+
+    /// Calls `f` with what each move from the position with key `key` leads to.
+    fn for_each_child(&self, key: u32, mut f: impl FnMut(Child)) {
+        let cells = self.geometry.cells();
+        let (mut x, mut o, mut rest) = (0u32, 0u32, key);
+        for cell in 0..cells {
+            match rest % 3 {
+                1 => x |= 1 << cell,
+                2 => o |= 1 << cell,
+                _ => (),
+            }
+            rest /= 3;
+        }
+        // X moves first, so X is to move when both have as many marks
+        let (mover, digit) = if x.count_ones() == o.count_ones() {
+            (x, 1)
+        } else {
+            (o, 2)
+        };
+        let symmetric_keys: Vec<u32> = self
+            .place_values
+            .iter()
+            .map(|values| {
+                (0..cells)
+                    .map(|cell| (x >> cell & 1) * values[cell] + (o >> cell & 1) * 2 * values[cell])
+                    .sum()
+            })
+            .collect();
+        let full = (x | o).count_ones() as usize + 1 == cells;
+        for cell in (0..cells).filter(|&cell| (x | o) >> cell & 1 == 0) {
+            let marks = mover | 1 << cell;
+            let won = self.geometry.lines_through(cell).iter().any(|&line| {
+                let line = self.lines[line];
+                marks & line == line
+            });
+            f(if won {
+                Child::Won
+            } else if full {
+                Child::Tied
+            } else {
+                Child::Ongoing(
+                    symmetric_keys
+                        .iter()
+                        .zip(&self.place_values)
+                        .map(|(key, values)| key + digit * values[cell])
+                        .min()
+                        .unwrap(),
+                )
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mech::Game;
+
+    fn bytes(tb: &Tablebase) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        tb.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn probe(tb: &Tablebase, notation: &str) -> Option<Outcome> {
+        tb.probe(notation.parse::<Game>().unwrap().position())
+    }
+
+    #[test]
+    fn known_outcomes() {
+        let tb = Tablebase::generate(3, 3, 3);
+        assert_eq!(probe(&tb, ".../.../... x"), Some(Outcome::Tie));
+        assert_eq!(probe(&tb, "xx./oo./... x"), Some(Outcome::Win(1)));
+        // O has to block, and then X makes two lines to complete
+        assert_eq!(probe(&tb, "xx./o../... o"), Some(Outcome::Loss(4)));
+        assert_eq!(probe(&tb, "xxx/oo./... o"), None);
+        assert_eq!(probe(&tb, "..../..../..../.... x"), None);
+    }
+
+    #[test]
+    fn shared_tablebases() {
+        let tb = Tablebase::shared(3, 3, 3).unwrap();
+        assert!(Arc::ptr_eq(&tb, &Tablebase::shared(3, 3, 3).unwrap()));
+        assert_eq!(probe(&tb, "xx./o../... o"), Some(Outcome::Loss(4)));
+        assert!(Tablebase::shared(5, 5, 4).is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let tb = Tablebase::generate(3, 4, 3);
+        let bytes = bytes(&tb);
+        let read = Tablebase::read_from(&bytes[..]).unwrap();
+        assert_eq!((read.rows(), read.cols(), read.win_length()), (3, 4, 3));
+        assert_eq!(read.len(), tb.len());
+        assert_eq!(self::bytes(&read), bytes);
+        let game = Game::with_win_length(3, 4, 3);
+        assert_eq!(read.probe(game.position()), tb.probe(game.position()));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let bytes = bytes(&Tablebase::generate(3, 3, 3));
+        let read = |bytes: &[u8]| Tablebase::read_from(bytes).unwrap_err();
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(matches!(read(&magic), TablebaseError::InvalidMagic));
+        let mut version = bytes.clone();
+        version[4] = Tablebase::VERSION + 1;
+        assert!(matches!(
+            read(&version),
+            TablebaseError::UnsupportedVersion(v) if v == Tablebase::VERSION + 1
+        ));
+        let mut size = bytes.clone();
+        size[5] = 2;
+        assert!(matches!(
+            read(&size),
+            TablebaseError::InvalidSize { rows: 2, .. }
+        ));
+        assert!(matches!(read(&bytes[..6]), TablebaseError::Io(_)));
+        assert!(matches!(
+            read(&bytes[..bytes.len() - 1]),
+            TablebaseError::Corrupt
+        ));
+        // the first two positions swapped
+        let mut unsorted = bytes.clone();
+        let entries = 12..22;
+        unsorted[entries.clone()].rotate_left(5);
+        assert!(matches!(read(&unsorted), TablebaseError::Corrupt));
+        let mut outcome = bytes.clone();
+        outcome[16] = 10;
+        assert!(matches!(read(&outcome), TablebaseError::Corrupt));
+    }
+
+    #[test]
+    fn incomplete_tablebase() {
+        // only the empty grid, whose key is 0, so it comes first
+        let mut bytes = bytes(&Tablebase::generate(3, 3, 3));
+        bytes.truncate(17);
+        bytes[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let tb = Tablebase::read_from(&bytes[..]).unwrap();
+        let game = Game::new(3, 3);
+        assert_eq!(tb.probe(game.position()), Some(Outcome::Tie));
+        assert_eq!(tb.probe_moves(game.position()), None);
+        assert_eq!(tb.best_moves(game.position()), None);
+    }
+}
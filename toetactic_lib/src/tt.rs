@@ -0,0 +1,152 @@
+//! Transposition table for caching search results
+
+use std::{
+    fmt::{self, Formatter},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Represents how a stored score relates to the true score of a position.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Bound {
+    /// The score is exact.
+    Exact,
+    /// The true score is at least the stored score (the search failed high).
+    Lower,
+    /// The true score is at most the stored score (the search failed low).
+    Upper,
+}
+
+/// Represents a cached search result.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Entry {
+    /// The Zobrist hash of the position.
+    pub key: u64,
+    /// The number of plies the position was searched to.
+    pub depth: u8,
+    pub score: i32,
+    pub bound: Bound,
+    /// The best move found in the position, as a cell index.
+    pub best: Option<u8>,
+}
+
+/// A fixed-size hash table of search results, indexed by Zobrist hash.
+/// When two positions map to the same slot, the newer one replaces the
+/// older one, unless they're the same position and the older entry was
+/// searched deeper.
+///
+/// The table can be shared between threads. Each entry is stored as two
+/// words, the packed entry and the packed entry XORed with its key, so
+/// an entry torn by two threads writing the same slot at once doesn't
+/// match its key and is ignored.
+pub struct TranspositionTable {
+    slots: Vec<[AtomicU64; 2]>,
+}
+
+/// Set in every packed entry, so that empty slots never match.
+const PRESENT: u64 = 1 << 63;
+
+impl Entry {
+    fn pack(self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let best = self.best.map_or(0, |cell| cell as u64 + 1);
+        PRESENT | best << 42 | bound << 40 | (self.depth as u64) << 32 | self.score as u32 as u64
+    }
+
+    fn unpack(key: u64, data: u64) -> Self {
+        Self {
+            key,
+            depth: (data >> 32) as u8,
+            score: data as u32 as i32,
+            bound: match (data >> 40) & 0b11 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            best: match (data >> 42) & 0x1ff {
+                0 => None,
+                cell => Some((cell - 1) as u8),
+            },
+        }
+    }
+}
+
+impl TranspositionTable {
+    /// The default number of entries, taking up 16 MiB.
+    pub const DEFAULT_CAPACITY: usize = 1 << 20;
+
+    /// Creates an empty table with room for `capacity` entries,
+    /// rounded down to a power of two.
+    /// **Panics** if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        let capacity = 1 << capacity.ilog2();
+        Self {
+            slots: (0..capacity).map(|_| Default::default()).collect(),
+        }
+    }
+
+    /// Returns the number of entries the table has room for.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, key: u64) -> &[AtomicU64; 2] {
+        &self.slots[key as usize & (self.slots.len() - 1)]
+    }
+
+    /// Returns the entry stored for the position with hash `key`, if any.
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let [check, data] = self.slot(key);
+        let data = data.load(Ordering::Relaxed);
+        let check = check.load(Ordering::Relaxed);
+        (data & PRESENT != 0 && check ^ data == key).then(|| Entry::unpack(key, data))
+    }
+
+    /// Stores `entry`, possibly replacing another one.
+    pub fn store(&self, entry: Entry) {
+        if self
+            .probe(entry.key)
+            .is_some_and(|old| old.depth > entry.depth)
+        {
+            return;
+        }
+        let [check, data] = self.slot(entry.key);
+        let packed = entry.pack();
+        data.store(packed, Ordering::Relaxed);
+        check.store(entry.key ^ packed, Ordering::Relaxed);
+    }
+
+    /// Removes every entry from the table.
+    pub fn clear(&mut self) {
+        for slot in &mut self.slots {
+            *slot = Default::default();
+        }
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl Clone for TranspositionTable {
+    fn clone(&self) -> Self {
+        let load = |word: &AtomicU64| AtomicU64::new(word.load(Ordering::Relaxed));
+        Self {
+            slots: self.slots.iter().map(|[a, b]| [load(a), load(b)]).collect(),
+        }
+    }
+}
+
+impl fmt::Debug for TranspositionTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TranspositionTable")
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}